@@ -15,6 +15,10 @@ const FILE_MASKS: [u64; 8] = [
     0x8080808080808080,
 ];
 
+// material in centipawns, indexed by Piece::to_index() - pawn, knight, bishop, rook, queen, king
+// the king can never be traded, so it has no material value
+const PIECE_VALUES: [i32; 6] = [100, 320, 330, 500, 900, 0];
+
 // passed pawn bonus depends on number of squares to promotion
 const PASSED_PAWN_BONUS: [i32; 8] = [0, 150, 90, 50, 20, 15, 15, 15];
 
@@ -59,6 +63,10 @@ const KING_PST: [i32; 64] = [
     -43, -79, -64, -32, -29, -32, -4, 3, -14, -50, -57, -18, 13, 4, 17, 30, -3, -14, 6, -1, 40, 18,
 ];
 
+pub fn piece_value(piece: Piece) -> i32 {
+    PIECE_VALUES[piece.to_index()]
+}
+
 pub fn evaluate_material(board: &Board) -> i32 {
     // Returns a positive value if the player whose turn it is is winning
     let mut total_score: i32 = 0;
@@ -79,13 +87,13 @@ pub fn evaluate_material(board: &Board) -> i32 {
                 total_score += match piece {
                     Pawn => {
                         interpolated_pawn_pst(endgame_factor, 63 - index)
-                            + 100
+                            + piece_value(Pawn)
                             + pawn_bonus_value(square, White, &black_pawns, &white_pawns)
                     }
-                    Knight => KNIGHT_PST[63 - index] + 320,
-                    Bishop => BISHOP_PST[63 - index] + 330,
-                    Rook => ROOK_PST[63 - index] + 500,
-                    Queen => QUEEN_PST[63 - index] + 900,
+                    Knight => KNIGHT_PST[63 - index] + piece_value(Knight),
+                    Bishop => BISHOP_PST[63 - index] + piece_value(Bishop),
+                    Rook => ROOK_PST[63 - index] + piece_value(Rook),
+                    Queen => QUEEN_PST[63 - index] + piece_value(Queen),
                     King => {
                         evaluate_king_position(63 - index, board, square, White, endgame_factor)
                     }
@@ -94,13 +102,13 @@ pub fn evaluate_material(board: &Board) -> i32 {
                 total_score -= match piece {
                     Pawn => {
                         interpolated_pawn_pst(endgame_factor, index)
-                            + 100
+                            + piece_value(Pawn)
                             + pawn_bonus_value(square, Black, &white_pawns, &black_pawns)
                     }
-                    Knight => KNIGHT_PST[index] + 320,
-                    Bishop => BISHOP_PST[index] + 330,
-                    Rook => ROOK_PST[index] + 500,
-                    Queen => QUEEN_PST[index] + 900,
+                    Knight => KNIGHT_PST[index] + piece_value(Knight),
+                    Bishop => BISHOP_PST[index] + piece_value(Bishop),
+                    Rook => ROOK_PST[index] + piece_value(Rook),
+                    Queen => QUEEN_PST[index] + piece_value(Queen),
                     King => evaluate_king_position(index, board, square, Black, endgame_factor),
                 };
             }
@@ -139,8 +147,8 @@ pub fn evaluate_material(board: &Board) -> i32 {
     // total_score += our_mobility - their_mobility;
 
     match board.side_to_move() {
-        White => return total_score,
-        Black => return -total_score,
+        White => total_score,
+        Black => -total_score,
     }
 }
 
@@ -182,8 +190,8 @@ fn king_safety(board: &Board, square: Square, king_color: Color) -> i32 {
 
     // TODO: tune
     // ends up being worth like 0-2 pawns
-    let safety = safety * safety_factor as i32;
-    return safety;
+
+    safety * safety_factor as i32
 }
 
 fn is_passed_pawn(enemy_pawns: &BitBoard, pawn_square: Square, pawn_color: Color) -> bool {
@@ -235,7 +243,7 @@ fn pawn_bonus_value(
         1 => -6,
         _ => 0,
     };
-    return bonus_value;
+    bonus_value
 }
 
 fn endgame_king_modifier(king_square: Square, endgame_factor: u32) -> i32 {
@@ -0,0 +1,230 @@
+use chess::{Board, BoardStatus, ChessMove, MoveGen, Square};
+use log::debug;
+use std::time::{Duration, Instant};
+
+// proof and disproof numbers this big mean the node can never be proven or disproven
+const INFINITY: u32 = u32::MAX / 2;
+// give up once the tree gets this big, around 160 megabytes
+const MAX_NODES: usize = 5_000_000;
+
+pub enum MateResult {
+    // the mating line, starting with our move
+    Mate(Vec<ChessMove>),
+    // every one of our moves has a defense
+    NoMate,
+    // ran out of time or memory before finding out
+    Unknown,
+}
+
+struct Node {
+    // the move that led to this position
+    mv: ChessMove,
+    // how many more leaves need to be proven to prove mate from here, or disproven to disprove it
+    proof: u32,
+    disproof: u32,
+    // children are stored next to each other in the tree
+    first_child: usize,
+    num_children: usize,
+    expanded: bool,
+}
+
+// proof-number search - the attacker needs just one move that mates against every defense,
+// so we always expand whichever leaf would do the most to settle that one way or the other
+pub struct MateSolver {
+    tree: Vec<Node>,
+    // plies the attacker has to deliver mate in, always odd
+    max_plies: usize,
+}
+
+impl Default for MateSolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MateSolver {
+    pub fn new() -> Self {
+        Self {
+            tree: Vec::new(),
+            max_plies: 1,
+        }
+    }
+
+    pub fn find_mate(
+        &mut self,
+        board: &Board,
+        max_moves: usize,
+        think_time: Duration,
+    ) -> (usize, MateResult) {
+        // try shorter mates first so whatever we find is the fastest mate
+        let kill_time = Instant::now() + think_time;
+        let mut result = (0, MateResult::NoMate);
+        for moves in 1..=max_moves {
+            match self.solve(board, moves, kill_time) {
+                MateResult::Mate(line) => return (moves, MateResult::Mate(line)),
+                MateResult::NoMate => debug!("No mate in {}", moves),
+                MateResult::Unknown => {
+                    debug!("Couldn't finish searching for mate in {}", moves);
+                    result = (moves, MateResult::Unknown);
+                    break;
+                }
+            }
+        }
+        result
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.tree.len()
+    }
+
+    fn solve(&mut self, board: &Board, moves: usize, kill_time: Instant) -> MateResult {
+        // prove or disprove mate in exactly this many moves or fewer
+        self.max_plies = 2 * moves - 1;
+        self.tree.clear();
+        let mut root = self.new_node(board, ChessMove::new(Square::A1, Square::A1, None), 0);
+        if board.status() != BoardStatus::Ongoing {
+            root.proof = INFINITY;
+            root.disproof = 0;
+        }
+        self.tree.push(root);
+
+        while self.tree[0].proof != 0 && self.tree[0].disproof != 0 {
+            if Instant::now() > kill_time || self.tree.len() >= MAX_NODES {
+                return MateResult::Unknown;
+            }
+            // walk down to the most proving leaf
+            let mut path = vec![0];
+            let mut leaf_board = *board;
+            while self.tree[*path.last().unwrap()].expanded {
+                let node = *path.last().unwrap();
+                let child = self.most_proving_child(node, path.len() - 1);
+                leaf_board = leaf_board.make_move_new(self.tree[child].mv);
+                path.push(child);
+            }
+            self.expand(*path.last().unwrap(), &leaf_board, path.len() - 1);
+            // then update everything above it
+            for (ply, node) in path.iter().enumerate().rev() {
+                self.update(*node, ply);
+            }
+        }
+
+        if self.tree[0].proof == 0 {
+            MateResult::Mate(self.mating_line())
+        } else {
+            MateResult::NoMate
+        }
+    }
+
+    fn new_node(&self, board: &Board, mv: ChessMove, ply: usize) -> Node {
+        // the attacker moves on even plies
+        let attacker_to_move = ply.is_multiple_of(2);
+        let (proof, disproof) = match board.status() {
+            BoardStatus::Checkmate if attacker_to_move => (INFINITY, 0),
+            BoardStatus::Checkmate => (0, INFINITY),
+            BoardStatus::Stalemate => (INFINITY, 0),
+            // out of moves without mating
+            BoardStatus::Ongoing if ply >= self.max_plies => (INFINITY, 0),
+            BoardStatus::Ongoing => {
+                // positions with fewer moves are quicker to settle
+                let num_moves = MoveGen::new_legal(board).len() as u32;
+                if attacker_to_move {
+                    (1, num_moves)
+                } else {
+                    (num_moves, 1)
+                }
+            }
+        };
+        Node {
+            mv,
+            proof,
+            disproof,
+            first_child: 0,
+            num_children: 0,
+            expanded: false,
+        }
+    }
+
+    fn expand(&mut self, node: usize, board: &Board, ply: usize) {
+        let first_child = self.tree.len();
+        for mv in MoveGen::new_legal(board) {
+            let child = self.new_node(&board.make_move_new(mv), mv, ply + 1);
+            self.tree.push(child);
+        }
+        self.tree[node].first_child = first_child;
+        self.tree[node].num_children = self.tree.len() - first_child;
+        self.tree[node].expanded = true;
+    }
+
+    fn children(&self, node: usize) -> std::ops::Range<usize> {
+        self.tree[node].first_child..self.tree[node].first_child + self.tree[node].num_children
+    }
+
+    fn update(&mut self, node: usize, ply: usize) {
+        if !self.tree[node].expanded {
+            return;
+        }
+        let children = self.children(node);
+        let min_proof = children.clone().map(|c| self.tree[c].proof).min();
+        let min_disproof = children.clone().map(|c| self.tree[c].disproof).min();
+        let sum_proof = children
+            .clone()
+            .fold(0, |sum, c| (sum + self.tree[c].proof).min(INFINITY));
+        let sum_disproof = children.fold(0, |sum, c| (sum + self.tree[c].disproof).min(INFINITY));
+        let (proof, disproof) = if ply.is_multiple_of(2) {
+            // we only need one of our moves to work
+            (min_proof.unwrap_or(INFINITY), sum_disproof)
+        } else {
+            // every defense has to fail
+            (sum_proof, min_disproof.unwrap_or(INFINITY))
+        };
+        self.tree[node].proof = proof;
+        self.tree[node].disproof = disproof;
+    }
+
+    fn most_proving_child(&self, node: usize, ply: usize) -> usize {
+        let children = self.children(node);
+        if ply.is_multiple_of(2) {
+            children.min_by_key(|c| self.tree[*c].proof).unwrap()
+        } else {
+            children.min_by_key(|c| self.tree[*c].disproof).unwrap()
+        }
+    }
+
+    fn mate_length(&self, node: usize, ply: usize) -> usize {
+        // plies until mate with best play from both sides, for a proven node
+        if !self.tree[node].expanded {
+            return 0;
+        }
+        let lengths = self
+            .children(node)
+            .filter(|c| self.tree[*c].proof == 0)
+            .map(|c| 1 + self.mate_length(c, ply + 1));
+        if ply.is_multiple_of(2) {
+            lengths.min().unwrap_or(0)
+        } else {
+            lengths.max().unwrap_or(0)
+        }
+    }
+
+    fn mating_line(&self) -> Vec<ChessMove> {
+        // we take the quickest mate, they hold out as long as possible
+        let mut line = Vec::new();
+        let mut node = 0;
+        let mut ply: usize = 0;
+        while self.tree[node].expanded {
+            let proven_children = self.children(node).filter(|c| self.tree[*c].proof == 0);
+            let next = if ply.is_multiple_of(2) {
+                proven_children.min_by_key(|c| self.mate_length(*c, ply + 1))
+            } else {
+                proven_children.max_by_key(|c| self.mate_length(*c, ply + 1))
+            };
+            let Some(next) = next else {
+                break;
+            };
+            line.push(self.tree[next].mv);
+            node = next;
+            ply += 1;
+        }
+        line
+    }
+}
@@ -0,0 +1,331 @@
+use crate::crab_evaluate;
+use crate::crab_ordering::{HistoryTable, MovePicker, Stage};
+use crate::crab_repetition::{is_irreversible, GameStart, PositionHistory, FIFTY_MOVE_PLIES};
+use chess::{Board, BoardStatus, ChessMove, Game, MoveGen, Square};
+use log::{debug, trace};
+use std::time::{Duration, Instant};
+
+// exploration constant, higher looks at more moves and lower looks deeper into the best ones
+const PUCT_CONSTANT: f32 = 1.5;
+// unvisited moves are assumed to be this much worse than their parent
+const FIRST_PLAY_URGENCY_REDUCTION: f32 = 0.1;
+// centipawns -> win probability, an advantage this big wins about 90% of the time
+const WIN_PROBABILITY_SCALE: f32 = 400.0;
+// priors are a softmax over how each move looks to the static evaluator
+const POLICY_TEMPERATURE: f32 = 100.0;
+// captures that can be made from a leaf before it's evaluated
+const LEAF_QUIESCENCE_DEPTH: usize = 4;
+// stop expanding the tree once it's this big, around 80 megabytes
+const MAX_NODES: usize = 2_000_000;
+// send an info line to uci this often
+const INFO_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Node {
+    // the move that led to this position
+    mv: ChessMove,
+    prior: f32,
+    visits: u32,
+    // from the point of view of whoever made `mv`, 1 is a win and 0 is a loss
+    value_sum: f32,
+    // children are stored next to each other in the tree
+    first_child: usize,
+    num_children: usize,
+    expanded: bool,
+}
+
+impl Node {
+    fn new(mv: ChessMove, prior: f32) -> Self {
+        Self {
+            mv,
+            prior,
+            visits: 0,
+            value_sum: 0.0,
+            first_child: 0,
+            num_children: 0,
+            expanded: false,
+        }
+    }
+
+    fn q(&self) -> f32 {
+        self.value_sum / self.visits as f32
+    }
+}
+
+pub struct CrabMcts {
+    tree: Vec<Node>,
+    position_history: PositionHistory,
+    game_start: GameStart,
+    // move pickers want one, but it stays empty since we only ever look at captures
+    history: HistoryTable,
+    max_depth: usize,
+}
+
+impl Default for CrabMcts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CrabMcts {
+    pub fn new() -> Self {
+        Self {
+            tree: Vec::new(),
+            position_history: PositionHistory::new(),
+            game_start: GameStart::default(),
+            history: HistoryTable::new(),
+            max_depth: 0,
+        }
+    }
+
+    pub fn set_game_start(&mut self, game_start: GameStart) {
+        self.game_start = game_start;
+    }
+
+    pub fn search(&mut self, board: &Board, game: &Game, think_time: Duration) -> (i32, ChessMove) {
+        // grow a tree from this position until time runs out, then play the most visited move
+        let start_time = Instant::now();
+        let kill_time = start_time + think_time;
+        let mut next_info = start_time + INFO_INTERVAL;
+        self.position_history = PositionHistory::from_game(game, self.game_start);
+        self.tree.clear();
+        self.tree
+            .push(Node::new(ChessMove::new(Square::A1, Square::A1, None), 1.0));
+        self.max_depth = 0;
+        debug!("MCTS from position {}", board);
+
+        let mut iterations: u64 = 0;
+        while Instant::now() < kill_time {
+            self.playout(board);
+            iterations += 1;
+            if self.tree[0].num_children <= 1 && iterations > 1 {
+                // only one legal move, or none
+                break;
+            }
+            if Instant::now() > next_info {
+                self.print_info(start_time);
+                next_info += INFO_INTERVAL;
+            }
+        }
+        self.print_info(start_time);
+
+        let best_move = match self.most_visited_child(0) {
+            Some(child) => self.tree[child].mv,
+            None => MoveGen::new_legal(board).next().unwrap_or(ChessMove::new(
+                Square::A1,
+                Square::A1,
+                None,
+            )),
+        };
+        let score = self.root_score();
+        debug!(
+            "MCTS chose {} @ {} after {} playouts, {} nodes - {:?}",
+            best_move,
+            score,
+            iterations,
+            self.tree.len(),
+            start_time.elapsed()
+        );
+        (score, best_move)
+    }
+
+    fn playout(&mut self, root: &Board) {
+        // walk down the tree picking the most promising moves, evaluate the position we end up in,
+        // then update every node on the way back up
+        let mut path = vec![0];
+        let mut board = *root;
+        let value = loop {
+            let node = *path.last().unwrap();
+            if !self.tree[node].expanded {
+                break self.expand(node, &board);
+            }
+            if self.tree[node].num_children == 0 {
+                // checkmate or stalemate, expanding already worked out who won
+                break self.terminal_value(&board);
+            }
+            let child = self.select_child(node);
+            let mv = self.tree[child].mv;
+            let irreversible = is_irreversible(&board, mv);
+            let next_board = board.make_move_new(mv);
+            let is_draw = self
+                .position_history
+                .is_repetition(next_board.get_hash(), irreversible);
+            self.position_history
+                .push(next_board.get_hash(), irreversible);
+            path.push(child);
+            board = next_board;
+            if is_draw || self.position_history.halfmove_clock() >= FIFTY_MOVE_PLIES {
+                break 0.5;
+            }
+        };
+        self.max_depth = self.max_depth.max(path.len() - 1);
+
+        // value is from the point of view of whoever moved into the last node
+        let mut value = value;
+        for node in path.iter().rev() {
+            self.tree[*node].visits += 1;
+            self.tree[*node].value_sum += value;
+            value = 1.0 - value;
+        }
+        for _ in 1..path.len() {
+            self.position_history.pop();
+        }
+    }
+
+    fn expand(&mut self, node: usize, board: &Board) -> f32 {
+        // add every legal move as a child and return the value of this position
+        self.tree[node].expanded = true;
+        if board.status() != BoardStatus::Ongoing {
+            return self.terminal_value(board);
+        }
+        if self.tree.len() >= MAX_NODES {
+            // out of room, keep evaluating this leaf instead
+            self.tree[node].expanded = false;
+            return 1.0 - self.leaf_value(board);
+        }
+        let moves: Vec<(ChessMove, f32)> = MoveGen::new_legal(board)
+            .map(|mv| {
+                // how good this move looks for us, before any search
+                let score = -crab_evaluate::evaluate_material(&board.make_move_new(mv));
+                (mv, score as f32 / POLICY_TEMPERATURE)
+            })
+            .collect();
+        let max_logit = moves
+            .iter()
+            .map(|(_, logit)| *logit)
+            .fold(f32::MIN, f32::max);
+        let total: f32 = moves
+            .iter()
+            .map(|(_, logit)| (logit - max_logit).exp())
+            .sum();
+        self.tree[node].first_child = self.tree.len();
+        self.tree[node].num_children = moves.len();
+        for (mv, logit) in moves {
+            self.tree
+                .push(Node::new(mv, (logit - max_logit).exp() / total));
+        }
+        1.0 - self.leaf_value(board)
+    }
+
+    fn select_child(&self, node: usize) -> usize {
+        // puct - balance how well a move has done against how likely the policy thinks it is
+        let parent = &self.tree[node];
+        let sqrt_visits = (parent.visits as f32).sqrt();
+        // unvisited moves get the parent's value, from the side to move's point of view
+        let parent_q = if parent.visits > 0 {
+            1.0 - parent.q()
+        } else {
+            0.5
+        };
+        let first_play_urgency = (parent_q - FIRST_PLAY_URGENCY_REDUCTION).max(0.0);
+        let children = parent.first_child..parent.first_child + parent.num_children;
+        children
+            .max_by(|a, b| {
+                self.puct_score(*a, sqrt_visits, first_play_urgency)
+                    .total_cmp(&self.puct_score(*b, sqrt_visits, first_play_urgency))
+            })
+            .unwrap()
+    }
+
+    fn puct_score(&self, child: usize, sqrt_parent_visits: f32, first_play_urgency: f32) -> f32 {
+        let child = &self.tree[child];
+        let q = if child.visits > 0 {
+            child.q()
+        } else {
+            first_play_urgency
+        };
+        q + PUCT_CONSTANT * child.prior * sqrt_parent_visits / (1 + child.visits) as f32
+    }
+
+    fn terminal_value(&self, board: &Board) -> f32 {
+        // from the point of view of whoever just moved
+        match board.status() {
+            BoardStatus::Checkmate => 1.0,
+            _ => 0.5,
+        }
+    }
+
+    fn leaf_value(&mut self, board: &Board) -> f32 {
+        // win probability for the side to move, after a short capture search
+        let score = self.quiescence(board, -i32::MAX, i32::MAX, LEAF_QUIESCENCE_DEPTH);
+        win_probability(score)
+    }
+
+    fn quiescence(&mut self, board: &Board, mut alpha: i32, beta: i32, depth: usize) -> i32 {
+        // only good captures, so the leaf isn't evaluated in the middle of a trade
+        let stand_pat = crab_evaluate::evaluate_material(board);
+        if depth == 0 || stand_pat >= beta {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+        let mut best = stand_pat;
+        let mut move_picker = MovePicker::new_captures();
+        while let Some((mv, stage)) = move_picker.next(board, &self.history) {
+            if stage != Stage::GoodCaptures {
+                break;
+            }
+            let score = -self.quiescence(&board.make_move_new(mv), -beta, -alpha, depth - 1);
+            if score >= beta {
+                return score;
+            }
+            best = best.max(score);
+            alpha = alpha.max(score);
+        }
+        best
+    }
+
+    fn most_visited_child(&self, node: usize) -> Option<usize> {
+        let node = &self.tree[node];
+        (node.first_child..node.first_child + node.num_children)
+            .max_by_key(|child| self.tree[*child].visits)
+    }
+
+    fn root_score(&self) -> i32 {
+        // the most visited move's value, back in centipawns
+        match self.most_visited_child(0) {
+            Some(child) if self.tree[child].visits > 0 => centipawns(self.tree[child].q()),
+            _ => 0,
+        }
+    }
+
+    fn principal_variation(&self) -> Vec<ChessMove> {
+        let mut pv = Vec::new();
+        let mut node = 0;
+        while let Some(child) = self.most_visited_child(node) {
+            if self.tree[child].visits == 0 {
+                break;
+            }
+            pv.push(self.tree[child].mv);
+            node = child;
+        }
+        pv
+    }
+
+    fn print_info(&self, start_time: Instant) {
+        let pv: Vec<String> = self
+            .principal_variation()
+            .iter()
+            .map(|mv| mv.to_string())
+            .collect();
+        trace!("MCTS root visits {}", self.tree[0].visits);
+        // for uci
+        println!(
+            "info depth {} seldepth {} score cp {} nodes {} time {} pv {}",
+            pv.len(),
+            self.max_depth,
+            self.root_score(),
+            self.tree[0].visits,
+            start_time.elapsed().as_millis(),
+            pv.join(" ")
+        );
+    }
+}
+
+fn win_probability(score: i32) -> f32 {
+    1.0 / (1.0 + 10f32.powf(-score as f32 / WIN_PROBABILITY_SCALE))
+}
+
+fn centipawns(win_probability: f32) -> i32 {
+    // the inverse of win_probability, clamped so certain wins don't become infinite
+    let p = win_probability.clamp(0.001, 0.999);
+    (WIN_PROBABILITY_SCALE * (p / (1.0 - p)).log10()) as i32
+}
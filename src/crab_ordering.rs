@@ -0,0 +1,288 @@
+use crate::crab_evaluate::piece_value;
+use crate::crab_see::static_exchange_evaluation;
+use chess::{BitBoard, Board, ChessMove, Color, MoveGen, Piece, Square, EMPTY};
+
+// killer moves are stored for every ply up to this one
+pub const MAX_KILLER_PLY: usize = 64;
+// history scores are kept between -HISTORY_MAX and HISTORY_MAX
+pub const HISTORY_MAX: i32 = 16384;
+
+pub struct KillerMoves([[Option<ChessMove>; 2]; MAX_KILLER_PLY]);
+
+impl Default for KillerMoves {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KillerMoves {
+    pub fn new() -> Self {
+        Self([[None; 2]; MAX_KILLER_PLY])
+    }
+
+    pub fn clear(&mut self) {
+        self.0 = [[None; 2]; MAX_KILLER_PLY];
+    }
+
+    pub fn store(&mut self, ply: usize, mv: ChessMove) {
+        if ply >= MAX_KILLER_PLY {
+            return;
+        }
+        let slots = &mut self.0[ply];
+        if slots[0] != Some(mv) {
+            // keep the previous killer around in the second slot
+            slots[1] = slots[0];
+            slots[0] = Some(mv);
+        }
+    }
+
+    pub fn get(&self, ply: usize) -> [Option<ChessMove>; 2] {
+        if ply >= MAX_KILLER_PLY {
+            return [None, None];
+        }
+        self.0[ply]
+    }
+}
+
+// butterfly history, indexed by [color][from square][to square]
+pub struct HistoryTable(Box<[[[i32; 64]; 64]; 2]>);
+
+impl Default for HistoryTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HistoryTable {
+    pub fn new() -> Self {
+        Self(Box::new([[[0; 64]; 64]; 2]))
+    }
+
+    pub fn get(&self, color: Color, mv: ChessMove) -> i32 {
+        self.0[color.to_index()][mv.get_source().to_index()][mv.get_dest().to_index()]
+    }
+
+    pub fn update(&mut self, color: Color, mv: ChessMove, bonus: i32) {
+        let entry =
+            &mut self.0[color.to_index()][mv.get_source().to_index()][mv.get_dest().to_index()];
+        // big scores grow slower so the table saturates at HISTORY_MAX
+        let bonus = bonus.clamp(-HISTORY_MAX, HISTORY_MAX);
+        *entry += bonus - *entry * bonus.abs() / HISTORY_MAX;
+    }
+
+    pub fn age(&mut self) {
+        // old searches should matter less than new ones
+        for entry in self.0.iter_mut().flatten().flatten() {
+            *entry /= 2;
+        }
+    }
+}
+
+// no chess position has more legal moves than this
+const MAX_MOVES: usize = 256;
+// captures by the king come after captures by anything else
+const ORDERING_KING_VALUE: i32 = 5000;
+// quiet queen promotions are tried before every other quiet move
+const QUEEN_PROMOTION_SCORE: i32 = 2 * HISTORY_MAX;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Stage {
+    HashMove,
+    GenerateCaptures,
+    GoodCaptures,
+    GenerateQuiets,
+    Killers,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+pub struct MovePicker {
+    stage: Stage,
+    captures_only: bool,
+    hash_move: Option<ChessMove>,
+    // both killers for this ply, then the move that refuted our previous sibling
+    killers: [Option<ChessMove>; 3],
+    killer_index: usize,
+    movegen: Option<MoveGen>,
+    moves: [(ChessMove, i32); MAX_MOVES],
+    current: usize,
+    end: usize,
+    // losing captures are moved to the front of `moves` as the good captures are used up
+    num_bad_captures: usize,
+    bad_capture_index: usize,
+}
+
+impl MovePicker {
+    pub fn new(
+        hash_move: Option<ChessMove>,
+        killers: [Option<ChessMove>; 2],
+        suggested_move: Option<ChessMove>,
+    ) -> Self {
+        Self {
+            stage: Stage::HashMove,
+            captures_only: false,
+            hash_move,
+            killers: [killers[0], killers[1], suggested_move],
+            killer_index: 0,
+            movegen: None,
+            moves: [(ChessMove::new(Square::A1, Square::A1, None), 0); MAX_MOVES],
+            current: 0,
+            end: 0,
+            num_bad_captures: 0,
+            bad_capture_index: 0,
+        }
+    }
+
+    pub fn new_captures() -> Self {
+        // for quiescence search, only look at captures
+        let mut picker = Self::new(None, [None, None], None);
+        picker.stage = Stage::GenerateCaptures;
+        picker.captures_only = true;
+        picker
+    }
+
+    pub fn next(&mut self, board: &Board, history: &HistoryTable) -> Option<(ChessMove, Stage)> {
+        loop {
+            match self.stage {
+                Stage::HashMove => {
+                    self.stage = Stage::GenerateCaptures;
+                    if let Some(hash_move) = self.hash_move {
+                        if is_legal(board, hash_move) {
+                            return Some((hash_move, Stage::HashMove));
+                        }
+                        self.hash_move = None;
+                    }
+                }
+                Stage::GenerateCaptures => {
+                    let mut movegen = MoveGen::new_legal(board);
+                    movegen.set_iterator_mask(*board.color_combined(!board.side_to_move()));
+                    for mv in &mut movegen {
+                        if Some(mv) != self.hash_move {
+                            self.moves[self.end] = (mv, capture_score(board, mv));
+                            self.end += 1;
+                        }
+                    }
+                    // whatever is left over are the quiet moves
+                    movegen.set_iterator_mask(!EMPTY);
+                    self.movegen = Some(movegen);
+                    self.stage = Stage::GoodCaptures;
+                }
+                Stage::GoodCaptures => {
+                    if self.current >= self.end {
+                        self.stage = if self.captures_only {
+                            Stage::BadCaptures
+                        } else {
+                            Stage::GenerateQuiets
+                        };
+                        continue;
+                    }
+                    let (mv, _) = self.pick_best();
+                    let victim = board.piece_on(mv.get_dest()).unwrap();
+                    let attacker = board.piece_on(mv.get_source()).unwrap();
+                    // capturing something at least as valuable can never lose material
+                    if ordering_value(victim) < ordering_value(attacker)
+                        && static_exchange_evaluation(board, mv) < 0
+                    {
+                        // save this for last
+                        self.moves[self.num_bad_captures] = (mv, 0);
+                        self.num_bad_captures += 1;
+                        continue;
+                    }
+                    return Some((mv, Stage::GoodCaptures));
+                }
+                Stage::GenerateQuiets => {
+                    self.current = self.end;
+                    if let Some(movegen) = self.movegen.as_mut() {
+                        for mv in movegen {
+                            if Some(mv) == self.hash_move {
+                                continue;
+                            }
+                            let score = if mv.get_promotion() == Some(Piece::Queen) {
+                                QUEEN_PROMOTION_SCORE
+                            } else {
+                                history.get(board.side_to_move(), mv)
+                            };
+                            self.moves[self.end] = (mv, score);
+                            self.end += 1;
+                        }
+                    }
+                    self.stage = Stage::Killers;
+                }
+                Stage::Killers => {
+                    if self.killer_index >= self.killers.len() {
+                        self.stage = Stage::Quiets;
+                        continue;
+                    }
+                    let killer = self.killers[self.killer_index];
+                    self.killer_index += 1;
+                    // killers come from other positions, so they're only used if they're a legal quiet move here
+                    if let Some(index) =
+                        (self.current..self.end).find(|i| Some(self.moves[*i].0) == killer)
+                    {
+                        self.moves.swap(self.current, index);
+                        self.current += 1;
+                        return Some((self.moves[self.current - 1].0, Stage::Killers));
+                    }
+                }
+                Stage::Quiets => {
+                    if self.current >= self.end {
+                        self.stage = Stage::BadCaptures;
+                        continue;
+                    }
+                    let (mv, _) = self.pick_best();
+                    return Some((mv, Stage::Quiets));
+                }
+                Stage::BadCaptures => {
+                    if self.bad_capture_index >= self.num_bad_captures {
+                        self.stage = Stage::Done;
+                        continue;
+                    }
+                    self.bad_capture_index += 1;
+                    return Some((self.moves[self.bad_capture_index - 1].0, Stage::BadCaptures));
+                }
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    fn pick_best(&mut self) -> (ChessMove, i32) {
+        // selection sort one move at a time, most nodes cut off before the list is sorted
+        let mut best_index = self.current;
+        for index in self.current + 1..self.end {
+            if self.moves[index].1 > self.moves[best_index].1 {
+                best_index = index;
+            }
+        }
+        self.moves.swap(self.current, best_index);
+        self.current += 1;
+        self.moves[self.current - 1]
+    }
+}
+
+fn ordering_value(piece: Piece) -> i32 {
+    match piece {
+        Piece::King => ORDERING_KING_VALUE,
+        _ => piece_value(piece),
+    }
+}
+
+fn capture_score(board: &Board, mv: ChessMove) -> i32 {
+    // for captures, score is enemy piece value minus a fraction of our piece value
+    // capturing cheap pieces with valuable pieces is likely a bad idea
+    let victim = board.piece_on(mv.get_dest()).unwrap();
+    let attacker = board.piece_on(mv.get_source()).unwrap();
+    let mut score = ordering_value(victim) - ordering_value(attacker) / 2;
+    // promotions are good
+    if mv.get_promotion() == Some(Piece::Queen) {
+        score += piece_value(Piece::Queen);
+    }
+    score
+}
+
+pub fn is_legal(board: &Board, mv: ChessMove) -> bool {
+    // only generate moves that land on the same square, which is much cheaper than all of them
+    let mut movegen = MoveGen::new_legal(board);
+    movegen.set_iterator_mask(BitBoard::from_square(mv.get_dest()));
+    movegen.any(|legal_mv| legal_mv == mv)
+}
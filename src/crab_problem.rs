@@ -0,0 +1,157 @@
+use chess::{Board, BoardStatus, ChessMove, Color, MoveGen};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProblemKind {
+    // both sides work together so the side to move gets mated
+    Helpmate,
+    // the side to move forces the other side to mate it, while the other side tries not to
+    Selfmate,
+}
+
+// exhaustive solver for chess problems in n moves, where the side to move in the starting
+// position is the side that ends up mated
+pub struct ProblemSolver {
+    kind: ProblemKind,
+    mated_side: Color,
+    // (position, plies left) -> can the problem still be solved from here
+    // positions reached by different move orders only get searched once
+    transpositions: HashMap<(u64, usize), bool>,
+    nodes: u64,
+}
+
+impl ProblemSolver {
+    pub fn new(kind: ProblemKind) -> Self {
+        Self {
+            kind,
+            mated_side: Color::White,
+            transpositions: HashMap::new(),
+            nodes: 0,
+        }
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    pub fn solve(&mut self, board: &Board, moves: usize) -> Vec<Vec<ChessMove>> {
+        // every solution, as full lines for helpmates or key moves with their main line for selfmates
+        // an empty list means there is provably no solution
+        self.mated_side = board.side_to_move();
+        self.transpositions.clear();
+        self.nodes = 0;
+        let plies = 2 * moves;
+        let mut solutions = Vec::new();
+        if plies == 0 {
+            return solutions;
+        }
+        match self.kind {
+            ProblemKind::Helpmate => {
+                self.helpmates(board, plies, &mut Vec::new(), &mut solutions);
+            }
+            ProblemKind::Selfmate => {
+                for key in MoveGen::new_legal(board) {
+                    let next = board.make_move_new(key);
+                    if self.selfmate_forced(&next, plies - 1) {
+                        let mut line = vec![key];
+                        line.extend(self.main_line(&next, plies - 1));
+                        solutions.push(line);
+                    }
+                }
+            }
+        }
+        solutions
+    }
+
+    fn helpmates(
+        &mut self,
+        board: &Board,
+        plies_left: usize,
+        line: &mut Vec<ChessMove>,
+        solutions: &mut Vec<Vec<ChessMove>>,
+    ) -> bool {
+        // collect every line from here that mates on exactly the last ply
+        self.nodes += 1;
+        if plies_left == 0 {
+            if board.status() == BoardStatus::Checkmate {
+                solutions.push(line.clone());
+                return true;
+            }
+            return false;
+        }
+        // mating early doesn't count, and neither does stalemate
+        if board.status() != BoardStatus::Ongoing {
+            return false;
+        }
+        let key = (board.get_hash(), plies_left);
+        if self.transpositions.get(&key) == Some(&false) {
+            return false;
+        }
+        let mut solved = false;
+        for mv in MoveGen::new_legal(board) {
+            line.push(mv);
+            solved |= self.helpmates(&board.make_move_new(mv), plies_left - 1, line, solutions);
+            line.pop();
+        }
+        self.transpositions.insert(key, solved);
+        solved
+    }
+
+    fn selfmate_forced(&mut self, board: &Board, plies_left: usize) -> bool {
+        // can the mated side force being mated within plies_left, however the other side defends?
+        self.nodes += 1;
+        match board.status() {
+            BoardStatus::Checkmate => return board.side_to_move() == self.mated_side,
+            BoardStatus::Stalemate => return false,
+            BoardStatus::Ongoing if plies_left == 0 => return false,
+            BoardStatus::Ongoing => (),
+        }
+        let key = (board.get_hash(), plies_left);
+        if let Some(forced) = self.transpositions.get(&key) {
+            return *forced;
+        }
+        let mut moves = MoveGen::new_legal(board);
+        let forced = if board.side_to_move() == self.mated_side {
+            moves.any(|mv| self.selfmate_forced(&board.make_move_new(mv), plies_left - 1))
+        } else {
+            moves.all(|mv| self.selfmate_forced(&board.make_move_new(mv), plies_left - 1))
+        };
+        self.transpositions.insert(key, forced);
+        forced
+    }
+
+    fn plies_to_selfmate(&mut self, board: &Board, plies_left: usize) -> Option<usize> {
+        // fewest plies the mated side needs from here, if it can force it at all
+        (0..=plies_left).find(|plies| self.selfmate_forced(board, *plies))
+    }
+
+    fn main_line(&mut self, board: &Board, plies_left: usize) -> Vec<ChessMove> {
+        // the mated side goes for the quickest mate and the other side holds out as long as it can
+        let mut line = Vec::new();
+        let mut board = *board;
+        let mut plies_left = plies_left;
+        while board.status() == BoardStatus::Ongoing && plies_left > 0 {
+            let mut lengths = Vec::new();
+            for mv in MoveGen::new_legal(&board) {
+                if let Some(plies) =
+                    self.plies_to_selfmate(&board.make_move_new(mv), plies_left - 1)
+                {
+                    lengths.push((mv, plies));
+                }
+            }
+            let lengths = lengths.into_iter();
+            let best = if board.side_to_move() == self.mated_side {
+                lengths.min_by_key(|(_, plies)| *plies)
+            } else {
+                lengths.max_by_key(|(_, plies)| *plies)
+            };
+            let Some((mv, _)) = best else {
+                break;
+            };
+            line.push(mv);
+            board = board.make_move_new(mv);
+            plies_left -= 1;
+        }
+        line
+    }
+}
@@ -0,0 +1,114 @@
+use chess::{Board, ChessMove, Game, Piece};
+
+// a draw by the fifty move rule after this many plies without a capture or pawn move
+pub const FIFTY_MOVE_PLIES: u32 = 100;
+
+// where the game being played started from, games can be set up from a fen
+#[derive(Clone, Copy, Default)]
+pub struct GameStart {
+    pub board: Board,
+    pub halfmove_clock: u32,
+}
+
+#[derive(Clone, Copy)]
+struct HistoryEntry {
+    key: u64,
+    // plies since the last capture or pawn move, nothing before that can come up again
+    halfmove_clock: u32,
+}
+
+// every position from the start of the game down to the node being searched
+// search threads push a position before searching it and pop it afterwards
+#[derive(Clone, Default)]
+pub struct PositionHistory {
+    entries: Vec<HistoryEntry>,
+    // index of the position being searched from, anything after it is inside the search tree
+    root_index: usize,
+}
+
+impl PositionHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_game(game: &Game, start: GameStart) -> Self {
+        // replay every move from the start of the game
+        let mut history = Self::new();
+        let mut board = start.board;
+        history.entries.push(HistoryEntry {
+            key: board.get_hash(),
+            halfmove_clock: start.halfmove_clock,
+        });
+        for action in game.actions() {
+            if let chess::Action::MakeMove(mv) = action {
+                if !board.legal(*mv) {
+                    break;
+                }
+                let irreversible = is_irreversible(&board, *mv);
+                board = board.make_move_new(*mv);
+                history.push(board.get_hash(), irreversible);
+            }
+        }
+        let current_position = game.current_position();
+        if board != current_position {
+            // we were given the wrong starting position somehow,
+            // so just start from the current position
+            history.entries.clear();
+            history.push(current_position.get_hash(), true);
+        }
+        history.root_index = history.entries.len() - 1;
+        history
+    }
+
+    pub fn push(&mut self, key: u64, irreversible: bool) {
+        let halfmove_clock = if irreversible {
+            0
+        } else {
+            self.halfmove_clock() + 1
+        };
+        self.entries.push(HistoryEntry {
+            key,
+            halfmove_clock,
+        });
+    }
+
+    pub fn pop(&mut self) {
+        self.entries.pop();
+    }
+
+    pub fn halfmove_clock(&self) -> u32 {
+        // for the position on top of the stack
+        self.entries.last().map_or(0, |entry| entry.halfmove_clock)
+    }
+
+    pub fn is_repetition(&self, key: u64, irreversible: bool) -> bool {
+        // would reaching this position next be a draw by repetition?
+        // a repeat of anything inside the search tree counts as a draw right away since
+        // whoever allowed it once could allow it again, but the game history needs threefold
+        if irreversible {
+            return false;
+        }
+        let mut repetitions = 0;
+        for index in (0..self.entries.len()).rev() {
+            let entry = self.entries[index];
+            // only positions with the same side to move can match
+            if (self.entries.len() - index).is_multiple_of(2) && entry.key == key {
+                if index >= self.root_index {
+                    return true;
+                }
+                repetitions += 1;
+                if repetitions >= 2 {
+                    return true;
+                }
+            }
+            if entry.halfmove_clock == 0 {
+                break;
+            }
+        }
+        false
+    }
+}
+
+pub fn is_irreversible(board: &Board, mv: ChessMove) -> bool {
+    board.piece_on(mv.get_dest()).is_some() || board.piece_on(mv.get_source()) == Some(Piece::Pawn)
+}
@@ -1,17 +1,79 @@
 use crate::crab_evaluate;
+use crate::crab_ordering::{is_legal, HistoryTable, KillerMoves, MovePicker, Stage, HISTORY_MAX};
+use crate::crab_repetition::{is_irreversible, GameStart, PositionHistory, FIFTY_MOVE_PLIES};
+use crate::crab_skill::Skill;
 use crate::crab_transposition;
-use chess::Piece::{Bishop, King, Knight, Pawn, Queen, Rook};
-use chess::{Board, BoardStatus, ChessMove, Game, MoveGen, Piece, Square, EMPTY};
+use chess::{Board, BoardStatus, ChessMove, Color, Game, MoveGen, Piece, Rank, Square, EMPTY};
 use crab_transposition::{NodeType, Transposition, TranspositionTable};
-use log::{debug, trace};
-use std::collections::HashMap;
+use log::{debug, info, trace, warn};
+use rand::rngs::StdRng;
+use rand::seq::IteratorRandom;
+use rand::SeedableRng;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 const MAXIMUM_SEARCH_DEPTH: usize = 40; // search will NEVER exceed this depth
 const CHECK_MV_SEARCH_DEPTH: usize = 20; // search will only evaluate captures (not check) after this depth
 
+// late move reductions only kick in at this remaining depth or higher
+const LMR_MIN_DEPTH: usize = 3;
+// the first few moves in the ordering are always searched at full depth
+const LMR_MIN_MOVE_INDEX: usize = 3;
+// larger divisor -> gentler reductions
+const LMR_DIVISOR: f64 = 2.25;
+
+// a single line can't be extended by more than this many plies in total
+const MAX_LINE_EXTENSIONS: usize = 6;
+// only look for singular moves with at least this much depth remaining
+const SINGULAR_MIN_DEPTH: usize = 6;
+// how far below the stored score every other move has to be, per ply of depth
+const SINGULAR_MARGIN: i32 = 2;
+
+// reverse futility pruning - at low depth, give up if static eval beats beta by this much per ply
+const RFP_MAX_DEPTH: usize = 3;
+const RFP_MARGIN: i32 = 120;
+// futility pruning - at low depth, skip quiet moves if static eval plus this can't reach alpha
+const FUTILITY_MARGINS: [i32; 4] = [0, 200, 350, 500];
+// without a hash move, pv nodes this deep run a shallower search first to find a move to try first
+const IID_MIN_DEPTH: usize = 5;
+const IID_REDUCTION: usize = 2;
+// and other nodes this deep are just searched one ply shallower
+const IIR_MIN_DEPTH: usize = 6;
+
+// probcut - at high depth, a capture that beats beta by this margin in a shallower search cuts the node
+const PROBCUT_MIN_DEPTH: usize = 5;
+const PROBCUT_MARGIN: i32 = 200;
+const PROBCUT_REDUCTION: usize = 4;
+
+// late move pruning - at low depth, stop looking at quiet moves after this many, indexed by depth
+const LMP_MOVE_COUNTS: [usize; 4] = [0, 6, 10, 16];
+// razoring - at low depth, drop into quiescence search if static eval is this far below alpha
+const RAZOR_MARGINS: [i32; 3] = [0, 300, 550];
+// delta pruning - skip captures in quiescence search that can't reach alpha even with this bonus
+const DELTA_MARGIN: i32 = 200;
+// scores beyond this are mate scores, which we shouldn't prune around
+pub const MATE_THRESHOLD: i32 = 999000;
+
+// how many quiet moves to penalize in the history table when another move cuts off
+const MAX_QUIETS_TRACKED: usize = 32;
+
+// evaluations shrink towards a draw once the halfmove clock gets this high, and since scores
+// then depend on the clock, the transposition table isn't used from here on either
+const FIFTY_MOVE_SCALE_START: u32 = 80;
+
+// root moves close to the best one are searched again with this fraction of the time and nodes
+// the search itself used, to get real scores for them
+const ROOT_RESEARCH_DIVISOR: u32 = 4;
+
+// deterministic searches stop after this many nodes unless told otherwise
+const DETERMINISTIC_NODE_BUDGET: u64 = 500_000;
+const DETERMINISTIC_TIME_LIMIT: Duration = Duration::from_secs(60 * 60 * 24);
+// and use this seed when the Seed option isn't set
+const DETERMINISTIC_SEED: u64 = 0;
+
 const STALEMATE_SCORE: i32 = 0;
-const CHECKMATE_SCORE: i32 = -999995;
+pub const CHECKMATE_SCORE: i32 = -999995;
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct SearchStats {
@@ -24,6 +86,18 @@ pub struct SearchStats {
     tt_lower_hits: i32,
     depth_reduction_misses: i32,
     depth_reduction_hits: i32,
+    extensions: i32,
+    singular_extensions: i32,
+    reverse_futility_prunes: i32,
+    futility_prunes: i32,
+    late_move_prunes: i32,
+    razor_prunes: i32,
+    iid_searches: i32,
+    internal_reductions: i32,
+    probcut_prunes: i32,
+    see_prunes: i32,
+    delta_prunes: i32,
+    qsearch_tt_hits: i32,
     max_ply: usize,
 }
 
@@ -40,97 +114,276 @@ impl std::ops::AddAssign for SearchStats {
         self.tt_lower_hits += other.tt_lower_hits;
         self.depth_reduction_misses += other.depth_reduction_misses;
         self.depth_reduction_hits += other.depth_reduction_hits;
+        self.extensions += other.extensions;
+        self.singular_extensions += other.singular_extensions;
+        self.reverse_futility_prunes += other.reverse_futility_prunes;
+        self.futility_prunes += other.futility_prunes;
+        self.late_move_prunes += other.late_move_prunes;
+        self.razor_prunes += other.razor_prunes;
+        self.iid_searches += other.iid_searches;
+        self.internal_reductions += other.internal_reductions;
+        self.probcut_prunes += other.probcut_prunes;
+        self.see_prunes += other.see_prunes;
+        self.delta_prunes += other.delta_prunes;
+        self.qsearch_tt_hits += other.qsearch_tt_hits;
         self.max_ply = self.max_ply.max(other.max_ply); // set ply to max instead of adding
     }
 }
 
 pub struct CrabChessSearch {
-    piece_values: HashMap<Piece, i32>,
-    transposition_table: TranspositionTable,
+    transposition_table: Arc<TranspositionTable>,
     trans_table_depth_threshold: usize,
     search_stats: SearchStats,
     cum_search_stats: SearchStats,
     current_search_depth: usize,
+    // how many plies to reduce a quiet move by, indexed by [depth][move index]
+    late_move_reductions: [[usize; 64]; 64],
+    killer_moves: KillerMoves,
+    history: HistoryTable,
+    // positions from the game and the line currently being searched, for repetition detection
+    position_history: PositionHistory,
+    game_start: GameStart,
+    // how much worse than 0 a draw is for the side we're choosing a move for
+    contempt: i32,
+    root_color: Color,
+    // limits how well we play when we're not supposed to be at full strength
+    skill: Skill,
+    node_limit: Option<u64>,
+    // search and quiescence search calls so far this move
+    nodes: u64,
+    // set when we run out of time or nodes partway through an iteration
+    aborted: bool,
+    driver: SearchDriver,
+    // for choosing a worse move on purpose, seeded so games can be reproduced
+    rng: StdRng,
+    seed: Option<u64>,
+    // stop after this many nodes no matter how much time is left
+    node_budget: Option<u64>,
+    // the same position always gets the same result, for testing
+    deterministic: bool,
+    // in the first few moves of a game, play any root move this close to the best one
+    variety_margin: i32,
+    variety_moves: usize,
+    // 0 is the main thread which reports to uci, anything else is a lazy smp helper thread
+    thread_index: usize,
+    helpers: Vec<CrabChessSearch>,
+    // shared by every thread, the main thread sets this when it's done so the helpers stop too
+    stop_search: Arc<AtomicBool>,
+}
+
+// how each iteration of iterative deepening finds its score
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SearchDriver {
+    // one full window search over every root move
+    AlphaBeta,
+    // a series of null window searches that close in on the score
+    Mtdf,
+}
+
+// the deepest fully searched iteration of one search thread
+struct DeepeningResult {
+    depth: usize,
+    score: i32,
+    best_move: ChessMove,
+    // every root move worth considering, best first
+    move_values: Vec<(ChessMove, i32)>,
+}
+
+impl Default for CrabChessSearch {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CrabChessSearch {
     pub fn new() -> CrabChessSearch {
-        CrabChessSearch {
+        let mut search = CrabChessSearch {
             // board: Board::default(),
-            piece_values: [
-                (Pawn, 100),
-                (Knight, 330),
-                (Bishop, 330),
-                (Rook, 500),
-                (Queen, 900),
-                (King, 5000),
-            ]
-            .iter()
-            .cloned()
-            .collect(),
-            transposition_table: TranspositionTable::new(),
+            transposition_table: Arc::new(TranspositionTable::new()),
             trans_table_depth_threshold: 2,
             search_stats: SearchStats::default(),
             cum_search_stats: SearchStats::default(),
             current_search_depth: 0,
+            late_move_reductions: calculate_late_move_reductions(),
+            killer_moves: KillerMoves::new(),
+            history: HistoryTable::new(),
+            position_history: PositionHistory::new(),
+            game_start: GameStart::default(),
+            contempt: 0,
+            root_color: Color::White,
+            skill: Skill::default(),
+            node_limit: None,
+            nodes: 0,
+            aborted: false,
+            driver: SearchDriver::AlphaBeta,
+            rng: StdRng::seed_from_u64(0),
+            seed: None,
+            node_budget: None,
+            deterministic: false,
+            variety_margin: 0,
+            variety_moves: 0,
+            thread_index: 0,
+            helpers: Vec::new(),
+            stop_search: Arc::new(AtomicBool::new(false)),
+        };
+        search.set_seed(None);
+        search
+    }
+
+    fn new_helper(&self, thread_index: usize) -> CrabChessSearch {
+        // helpers have their own move ordering tables but share the transposition table
+        CrabChessSearch {
+            transposition_table: Arc::clone(&self.transposition_table),
+            trans_table_depth_threshold: self.trans_table_depth_threshold,
+            search_stats: SearchStats::default(),
+            cum_search_stats: SearchStats::default(),
+            current_search_depth: 0,
+            late_move_reductions: self.late_move_reductions,
+            killer_moves: KillerMoves::new(),
+            history: HistoryTable::new(),
+            position_history: PositionHistory::new(),
+            game_start: GameStart::default(),
+            contempt: self.contempt,
+            root_color: self.root_color,
+            skill: self.skill,
+            node_limit: self.node_limit,
+            nodes: 0,
+            aborted: false,
+            driver: self.driver,
+            rng: StdRng::seed_from_u64(0),
+            seed: None,
+            node_budget: None,
+            deterministic: false,
+            variety_margin: self.variety_margin,
+            variety_moves: self.variety_moves,
+            thread_index,
+            helpers: Vec::new(),
+            stop_search: Arc::clone(&self.stop_search),
         }
     }
 
-    fn get_moves_lazily_ordered(
-        &self,
-        board: &Board,
-        move_iterator: MoveGen,
-        suggested_moves: Option<Vec<&ChessMove>>,
-    ) -> Vec<(ChessMove, i32)> {
-        // Pass in a MoveGen to grab moves from
-        // returns a vector of moves lazily ordered by guess of which is best
-        let mut guess_values: Vec<(ChessMove, i32)> = Vec::new();
-        let mut guess_score: i32;
-        let all_suggested_moves = match suggested_moves {
-            Some(suggested_move) => suggested_move,
-            None => vec![],
-        };
-        for mv in move_iterator {
-            // evaluating material here is too expensive
-            guess_score = 0;
+    pub fn set_threads(&mut self, threads: usize) {
+        // the main thread counts as one of the threads
+        self.helpers = (1..threads.max(1))
+            .map(|thread_index| self.new_helper(thread_index))
+            .collect();
+        debug!("Searching with {} threads", self.helpers.len() + 1);
+    }
 
-            // push suggested moves up
-            if all_suggested_moves.contains(&&mv) {
-                guess_score += 10000
-            }
+    pub fn set_game_start(&mut self, game_start: GameStart) {
+        self.game_start = game_start;
+    }
 
-            // promotions are good
-            if let Some(piece) = mv.get_promotion() {
-                if piece == Piece::Queen {
-                    guess_score += 900
-                }
-            }
+    pub fn set_contempt(&mut self, contempt: i32) {
+        self.contempt = contempt;
+        for helper in self.helpers.iter_mut() {
+            helper.contempt = contempt;
+        }
+        debug!("Contempt set to {}", contempt);
+    }
 
-            let move_target = mv.get_dest();
-            if let Some(piece) = board.piece_on(move_target) {
-                // for captures, score is enemy piece value minus a fraction of our piece value
-                // capturing cheap pieces with valuable pieces is likely a bad idea
-                guess_score += self.piece_values.get(&piece).unwrap()
-                    - (self
-                        .piece_values
-                        .get(&board.piece_on(mv.get_source()).unwrap())
-                        .unwrap()
-                        / 2);
-                // a capture that can be recaptured by an enemy is worse
-                // let pawn_attacks =
-                //     chess::get_pawn_attacks(move_target, board.side_to_move(), BitBoard::new(0));
-                // trace!("pawn attacks: {}", pawn_attacks)
-            }
-            guess_values.push((mv, guess_score))
+    pub fn set_skill(&mut self, skill: Skill) {
+        self.skill = skill;
+        for helper in self.helpers.iter_mut() {
+            helper.skill = skill;
         }
+        debug!("Skill set to {:?}", skill);
+    }
 
-        guess_values.sort_by(|a, b| b.1.cmp(&a.1));
-        let mut ordered_moves: Vec<(ChessMove, i32)> = Vec::new();
-        // order moves from best to worst
-        for (mv, score) in guess_values.iter() {
-            ordered_moves.push((*mv, *score))
+    pub fn set_variety(&mut self, margin: i32, moves: usize) {
+        self.variety_margin = margin;
+        self.variety_moves = moves;
+    }
+
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        // without a seed pick one at random, it's logged so the same random choices can be made again
+        self.seed = seed;
+        let seed = seed.unwrap_or_else(rand::random);
+        info!("Random seed {}", seed);
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn set_driver(&mut self, driver: SearchDriver) {
+        self.driver = driver;
+        for helper in self.helpers.iter_mut() {
+            helper.driver = driver;
         }
-        ordered_moves
+        debug!("Search driver set to {:?}", driver);
+    }
+
+    pub fn set_node_budget(&mut self, nodes: Option<u64>) {
+        self.node_budget = nodes;
+    }
+
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        // searches ignore the clock and only stop at the node budget,
+        // use one thread, and start with nothing left over from earlier searches
+        self.deterministic = deterministic;
+        debug!("Deterministic search {}", deterministic);
+    }
+
+    fn probe(&self, board: &Board) -> Option<Transposition> {
+        // only part of the key is stored, so now and then an entry for another position matches -
+        // its move is almost never legal here, and a cutoff can't return a move we can't play
+        self.transposition_table
+            .get(board.get_hash())
+            .filter(|transpo| {
+                transpo.best_move == ChessMove::new(Square::A1, Square::A1, None)
+                    || is_legal(board, transpo.best_move)
+            })
+    }
+
+    fn draw_score(&self, board: &Board) -> i32 {
+        // draws are scored from the point of view of the side to move on this board
+        if board.side_to_move() == self.root_color {
+            STALEMATE_SCORE - self.contempt
+        } else {
+            STALEMATE_SCORE + self.contempt
+        }
+    }
+
+    fn should_abort(&mut self, kill_time: &Instant) -> bool {
+        // once this is true, every frame returns straight away without storing anything
+        if !self.aborted && self.out_of_time(kill_time) {
+            self.aborted = true;
+        }
+        self.aborted
+    }
+
+    fn out_of_time(&self, kill_time: &Instant) -> bool {
+        kill_time.elapsed() > Duration::ZERO
+            || self.stop_search.load(Ordering::Relaxed)
+            || self.node_limit.is_some_and(|limit| self.nodes >= limit)
+    }
+
+    fn with_position<T>(
+        &mut self,
+        board: &Board,
+        irreversible: bool,
+        search: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        // keep this position on the history stack for as long as it's being searched
+        self.position_history.push(board.get_hash(), irreversible);
+        let result = search(self);
+        self.position_history.pop();
+        result
+    }
+
+    fn near_fifty_move_draw(&self) -> bool {
+        // scores here depend on how we got here, so they shouldn't be shared through the transposition table
+        self.position_history.halfmove_clock() >= FIFTY_MOVE_SCALE_START
+    }
+
+    fn evaluate(&mut self, board: &Board) -> i32 {
+        // shrink the evaluation as we get closer to a fifty move draw
+        // so that the search prefers making progress over shuffling
+        self.search_stats.boards_evaluated += 1;
+        let evaluation = crab_evaluate::evaluate_material(board);
+        if !self.near_fifty_move_draw() {
+            return evaluation;
+        }
+        let plies_left = FIFTY_MOVE_PLIES.saturating_sub(self.position_history.halfmove_clock());
+        evaluation * plies_left as i32 / (FIFTY_MOVE_PLIES - FIFTY_MOVE_SCALE_START) as i32
     }
 
     pub fn iterative_search_deepening(
@@ -141,11 +394,6 @@ impl CrabChessSearch {
         time_low_bar: Duration,
     ) -> (i32, ChessMove) {
         let start_time = Instant::now();
-        let mut score: i32 = 111111;
-        let mut move_order: Vec<(ChessMove, i32)> = Vec::new();
-        let movegen: MoveGen = MoveGen::new_legal(&board);
-        let mut chosen_move: ChessMove = ChessMove::new(Square::A1, Square::A1, None);
-        let mut best_resp;
 
         trace!("---- start search ----");
         debug!("Selecting a move from position {}", board.to_string());
@@ -153,40 +401,226 @@ impl CrabChessSearch {
         // debug!("DEBUG: clearing transpo table");
         // self.transposition_table = HashMap::new();
 
-        let kill_time = Instant::now() + time_low_bar;
-        for mv in self.get_moves_lazily_ordered(board, movegen, None) {
-            move_order.push(mv)
-        }
+        let kill_time = if self.deterministic {
+            // only the node budget can stop a deterministic search
+            start_time + DETERMINISTIC_TIME_LIMIT
+        } else {
+            Instant::now() + time_low_bar
+        };
         // if depth < 3 {
         //     panic!("depth must be >= 3");
         // }
-        // iteratively search at multiple depths
+        let node_budget = match self.node_budget {
+            None if self.deterministic => Some(DETERMINISTIC_NODE_BUDGET),
+            node_budget => node_budget,
+        };
+        self.node_limit = [self.skill.node_limit(), node_budget]
+            .into_iter()
+            .flatten()
+            .min();
+        if self.deterministic {
+            self.transposition_table.clear();
+            self.history = HistoryTable::new();
+            self.rng = StdRng::seed_from_u64(self.seed.unwrap_or(DETERMINISTIC_SEED));
+        }
+        self.transposition_table.new_search();
+
+        self.root_color = board.side_to_move();
+        self.position_history = PositionHistory::from_game(game, self.game_start);
 
-        // generate seen_positions
-        let mut seen_positions: HashMap<u64, u32> = HashMap::new();
-        let mut replay_game = chess::Game::new();
-        // replay all actions onto a new game to create seen_positions
-        for replay_action in game.actions() {
-            if let chess::Action::MakeMove(replay_mv) = replay_action {
-                let replay_board = replay_game.current_position();
-                if replay_board.piece_on(replay_mv.get_dest()).is_some() {
-                    // a capture move means we'll never see any of the previous positions again
-                    seen_positions.clear();
+        // skill and variety pick between root moves close to the best one
+        let use_variety = self.variety_margin > 0 && game.actions().len() < 2 * self.variety_moves;
+        let margin = [
+            self.skill.max_loss(),
+            use_variety.then_some(self.variety_margin),
+        ]
+        .into_iter()
+        .flatten()
+        .max();
+        // mtd(f) only scores the best root move, they need a score for every one of them
+        let driver = self.driver;
+        if margin.is_some() && driver == SearchDriver::Mtdf {
+            warn!("Skill and Variety don't work with MTD(f), using alpha-beta for this move");
+            self.set_driver(SearchDriver::AlphaBeta);
+        }
+
+        // lazy smp - every helper thread searches the same position, sharing what they find
+        // through the transposition table, and the main thread uses whichever went deepest
+        self.stop_search.store(false, Ordering::Relaxed);
+        let mut helpers = std::mem::take(&mut self.helpers);
+        let mut result = std::thread::scope(|scope| {
+            let helper_threads: Vec<_> = helpers
+                .iter_mut()
+                .filter(|_| !self.deterministic)
+                .map(|helper| {
+                    helper.node_limit = self.node_limit;
+                    helper.position_history = self.position_history.clone();
+                    helper.root_color = self.root_color;
+                    scope.spawn(move || helper.deepen(board, depth, &kill_time, start_time))
+                })
+                .collect();
+            let main_result = self.deepen(board, depth, &kill_time, start_time);
+            self.stop_search.store(true, Ordering::Relaxed);
+            // the main thread always finishes at least one iteration
+            let mut result = main_result.unwrap_or(DeepeningResult {
+                depth: 0,
+                score: 0,
+                best_move: ChessMove::new(Square::A1, Square::A1, None),
+                move_values: Vec::new(),
+            });
+            for helper_thread in helper_threads {
+                if let Ok(Some(helper_result)) = helper_thread.join() {
+                    if helper_result.depth > result.depth
+                        || (helper_result.depth == result.depth
+                            && helper_result.score > result.score)
+                    {
+                        result = helper_result;
+                    }
                 }
-                replay_game.make_move(*replay_mv);
-                *seen_positions
-                    .entry(replay_game.current_position().get_hash())
-                    .or_insert(0) += 1;
             }
+            result
+        });
+        for helper in helpers.iter_mut() {
+            self.cum_search_stats += helper.cum_search_stats;
+            helper.cum_search_stats = SearchStats::default();
+        }
+        self.helpers = helpers;
+        self.set_driver(driver);
+        if let Some(margin) = margin {
+            result.move_values = self.exact_root_scores(board, &result, margin, start_time);
+        }
+        if self.skill.is_limited() {
+            // play a worse move on purpose
+            if let Some(mv) = self.skill.pick_move(&result.move_values, &mut self.rng) {
+                if mv != result.best_move {
+                    debug!("Skill level picked {} instead of {}", mv, result.best_move);
+                }
+                result.best_move = mv;
+            }
+        }
+        if use_variety {
+            // so we don't play the same opening every game
+            if let Some(mv) =
+                pick_varied_move(&result.move_values, self.variety_margin, &mut self.rng)
+            {
+                if mv != result.best_move {
+                    debug!("Variety picked {} instead of {}", mv, result.best_move);
+                }
+                result.best_move = mv;
+            }
+        }
+        if result.best_move == ChessMove::new(Square::A1, Square::A1, None) {
+            // should never happen, but never give up without a move
+            result.best_move = MoveGen::new_legal(board).next().unwrap_or(result.best_move);
+        }
+        debug!(
+            "Chose {} @ {} from depth {}",
+            result.best_move, result.score, result.depth
+        );
+
+        debug!("Cumulative search stats:");
+        debug!("{:?}", self.cum_search_stats);
+        trace!("---- End search ----");
+        trace!("");
+        (result.score, result.best_move)
+    }
+
+    fn deepen(
+        &mut self,
+        board: &Board,
+        depth: usize,
+        kill_time: &Instant,
+        start_time: Instant,
+    ) -> Option<DeepeningResult> {
+        // iteratively search at multiple depths
+        // returns the result of the deepest iteration, helpers only count complete iterations
+        let is_main_thread = self.thread_index == 0;
+        let depth = match self.skill.max_depth() {
+            Some(max_depth) => depth.min(max_depth),
+            None => depth,
+        };
+        self.nodes = 0;
+        self.aborted = false;
+        let mut result: Option<DeepeningResult> = None;
+        let mut move_order: Vec<(ChessMove, i32)> = Vec::new();
+        let mut score: i32;
+        let mut chosen_move: ChessMove;
+        let mut best_resp;
+
+        // killers are only useful within one search, history is kept but matters less
+        self.killer_moves.clear();
+        self.history.age();
+        let mut move_picker = MovePicker::new(None, [None, None], None);
+        while let Some((mv, _)) = move_picker.next(board, &self.history) {
+            move_order.push((mv, 0))
         }
 
         for n in 2.min(depth)..=depth {
+            // half of the helpers stay a ply ahead of everyone else
+            let n = (n + self.thread_index % 2).min(depth);
             self.current_search_depth = n;
             // TODO: do i need to .step_by(2)?
 
             // execute a top level search
-            (score, chosen_move, move_order, best_resp) =
-                self.top_level_search(board, n, move_order, &kill_time, &seen_positions);
+            (score, chosen_move, move_order, best_resp) = match self.driver {
+                SearchDriver::AlphaBeta => self.top_level_search(board, n, move_order, kill_time),
+                SearchDriver::Mtdf => {
+                    // the last iteration's score is usually close to this one's
+                    let guess = result.as_ref().map_or(0, |previous| previous.score);
+                    let (score, mv) = self.mtdf(board, n, guess, kill_time);
+                    (
+                        score,
+                        mv,
+                        vec![(mv, score)],
+                        ChessMove::new(Square::A1, Square::A1, None),
+                    )
+                }
+            };
+            // add to cumulative search stats then clear search_stats for next time
+            self.cum_search_stats += self.search_stats;
+            self.search_stats = SearchStats::default();
+            if self.aborted {
+                // the previous best move is always searched first, so an unfinished iteration
+                // only changes our mind if another root move was fully searched and beat it
+                if chosen_move != ChessMove::new(Square::A1, Square::A1, None) {
+                    match result.as_mut() {
+                        Some(previous) if previous.best_move != chosen_move => {
+                            debug!("Switching to {} from an unfinished iteration", chosen_move);
+                            previous.best_move = chosen_move;
+                            previous.score = score;
+                        }
+                        Some(_) => {}
+                        None => {
+                            result = Some(DeepeningResult {
+                                depth: 0,
+                                score,
+                                best_move: chosen_move,
+                                move_values: Vec::new(),
+                            })
+                        }
+                    }
+                }
+                break;
+            }
+            if !is_main_thread {
+                result = Some(DeepeningResult {
+                    depth: n,
+                    score,
+                    best_move: chosen_move,
+                    move_values: move_order.clone(),
+                });
+                if n >= depth {
+                    break;
+                }
+                continue;
+            }
+            result = Some(DeepeningResult {
+                depth: n,
+                score,
+                best_move: chosen_move,
+                move_values: move_order.clone(),
+            });
+
             debug!(
                 "Depth: {} - {} -> {} @ {} - {:?}",
                 n,
@@ -197,10 +631,11 @@ impl CrabChessSearch {
             );
             // for uci
             println!(
-                "info depth {} seldepth {} score cp {} time {} pv {} {}",
+                "info depth {} seldepth {} score cp {} nodes {} time {} pv {} {}",
                 n,
                 self.cum_search_stats.max_ply,
                 score,
+                self.nodes,
                 start_time.elapsed().as_millis(),
                 chosen_move,
                 if best_resp == ChessMove::new(Square::A1, Square::A1, None) {
@@ -215,10 +650,6 @@ impl CrabChessSearch {
             // if score > 999000 {
             //     return (score, chosen_move);
             // }
-            // trace!("TT Size is now {}", self.transposition_table.0.len());
-            // add to cumulative search stats then clear search_stats for next time
-            self.cum_search_stats += self.search_stats;
-            self.search_stats = SearchStats::default();
 
             // debug!("best response: {}", best_resp);
             let mut move_scores_output = "Move scores: ".to_string();
@@ -230,16 +661,142 @@ impl CrabChessSearch {
             if n >= depth {
                 debug!("Reached maximum depth...");
                 break;
-            } else if kill_time.elapsed() > Duration::ZERO {
+            } else if self.out_of_time(kill_time) {
                 debug!("Too much time elapsed to continue search...");
                 break;
             }
         }
-        debug!("Cumulative search stats:");
-        debug!("{:?}", self.cum_search_stats);
-        trace!("---- End search ----");
-        trace!("");
-        return (score, chosen_move);
+        result
+    }
+
+    fn exact_root_scores(
+        &mut self,
+        board: &Board,
+        result: &DeepeningResult,
+        margin: i32,
+        start_time: Instant,
+    ) -> Vec<(ChessMove, i32)> {
+        // every root move after the first was searched against the best score so far, so its
+        // score is only an upper bound - search the ones that might be within the margin again
+        // with a window that gives them a real score, and drop anything further behind
+        if result.depth == 0 {
+            return Vec::new();
+        }
+        let best_score = result.score;
+        let floor = best_score - margin;
+        let kill_time = if self.deterministic {
+            start_time + DETERMINISTIC_TIME_LIMIT
+        } else {
+            Instant::now() + start_time.elapsed() / ROOT_RESEARCH_DIVISOR
+        };
+        let node_limit = self.node_limit;
+        self.node_limit = node_limit.map(|limit| limit / ROOT_RESEARCH_DIVISOR as u64);
+        self.nodes = 0;
+        self.aborted = false;
+        // the helpers are done by now
+        self.stop_search.store(false, Ordering::Relaxed);
+
+        let mut move_values = vec![(result.best_move, best_score)];
+        for (mv, upper_bound) in result.move_values.iter() {
+            if *mv == result.best_move || *upper_bound < floor {
+                continue;
+            }
+            let nboard = board.make_move_new(*mv);
+            let irreversible = is_irreversible(board, *mv);
+            let score = if self
+                .position_history
+                .is_repetition(nboard.get_hash(), irreversible)
+            {
+                self.draw_score(board)
+            } else {
+                // the same depth the move was searched at the first time
+                let capture = board.piece_on(mv.get_dest()).is_some();
+                let depth_modifier = capture as usize;
+                let (score, _) = self.with_position(&nboard, irreversible, |search| {
+                    search.search(
+                        &nboard,
+                        result.depth + depth_modifier - 1,
+                        1,
+                        -best_score - 1,
+                        -floor + 1,
+                        &kill_time,
+                        None,
+                        depth_modifier,
+                        capture.then_some(mv.get_dest()),
+                    )
+                });
+                -score
+            };
+            if self.aborted {
+                debug!("Ran out of time searching close root moves again");
+                break;
+            }
+            if score >= floor {
+                move_values.push((*mv, score.min(best_score)));
+            }
+        }
+        self.node_limit = node_limit;
+        self.cum_search_stats += self.search_stats;
+        self.search_stats = SearchStats::default();
+        move_values.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+        move_values
+    }
+
+    fn mtdf(
+        &mut self,
+        board: &Board,
+        depth: usize,
+        guess: i32,
+        kill_time: &Instant,
+    ) -> (i32, ChessMove) {
+        // every null window search tells us whether the score is above or below the guess,
+        // then we guess again until the bounds meet
+        // the transposition table keeps the repeated searches cheap
+        let default_move = ChessMove::new(Square::A1, Square::A1, None);
+        let mut score = guess;
+        let mut lower_bound = -999999777;
+        let mut upper_bound = 999999777;
+        let mut best_move = default_move;
+        while lower_bound < upper_bound {
+            let beta = if score == lower_bound {
+                score + 1
+            } else {
+                score
+            };
+            let mv;
+            (score, mv) = self.search(board, depth, 0, beta - 1, beta, kill_time, None, 0, None);
+            if self.aborted {
+                // a move from an unfinished mtd(f) search hasn't been compared to the others
+                return (score, default_move);
+            }
+            // never trust a root move that isn't legal here
+            let legal = board.legal(mv);
+            if score < beta {
+                upper_bound = score;
+                if best_move == default_move && legal {
+                    best_move = mv;
+                }
+            } else {
+                // only a search that failed high has actually found a move this good
+                lower_bound = score;
+                if legal {
+                    best_move = mv;
+                }
+            }
+        }
+        if depth >= self.trans_table_depth_threshold && !self.near_fifty_move_draw() {
+            self.search_stats.tt_pushed += 1;
+            self.transposition_table.insert(
+                board.get_hash(),
+                Transposition {
+                    depth,
+                    score,
+                    node_type: NodeType::Exact,
+                    best_move,
+                },
+            );
+        }
+        (score, best_move)
     }
 
     fn top_level_search(
@@ -248,7 +805,6 @@ impl CrabChessSearch {
         depth: usize,
         move_order: Vec<(ChessMove, i32)>,
         kill_time: &Instant,
-        seen_positions: &HashMap<u64, u32>,
     ) -> (i32, ChessMove, Vec<(ChessMove, i32)>, ChessMove) {
         let mut alpha = -999999777; // this must be worse than losing
         let beta = 999999777;
@@ -265,19 +821,17 @@ impl CrabChessSearch {
         // return move_values at the end, it'll be like the new version of move_order
         let mut move_values: Vec<(ChessMove, i32)> = Vec::new();
         // debug!("Searching {} moves at depth {}", move_order.len(), depth);
-        for (mv_index, (mv, mv_naive_score)) in move_order.iter().enumerate() {
+        for (mv, _mv_naive_score) in move_order.iter() {
             let nboard = board.make_move_new(*mv);
             let default_move = ChessMove::new(Square::A1, Square::A1, None);
 
-            // Check for threefold repetition
-            let (new_seen_positions, is_draw) =
-                match check_for_draw(seen_positions.clone(), &nboard) {
-                    Ok(previous_map) => (previous_map, false),
-                    Err(_) => (HashMap::new(), true),
-                };
+            let irreversible = is_irreversible(board, *mv);
+            let is_draw = self
+                .position_history
+                .is_repetition(nboard.get_hash(), irreversible);
 
             let (evaluation, this_response) = if is_draw {
-                (STALEMATE_SCORE, default_move)
+                (self.draw_score(board), default_move)
             } else {
                 alpha -= 1; // so that if mate in 2 is 9998 then mate in 3 is 9997
 
@@ -288,7 +842,7 @@ impl CrabChessSearch {
                     0
                 };
 
-                let mut needs_full_search = true;
+                let needs_full_search = true;
                 let mut move_search_score = 10101010; // this is ALWAYS overwritten
                 let mut best_response_mv = default_move; // this is ALWAYS overwritten
                                                          // but if i don't initialize them the compiler has a fit
@@ -301,35 +855,34 @@ impl CrabChessSearch {
                         depth_modifier = 0;
                         // self.search_stats.depth_reduction_misses += 1;
                     };
-                    (move_search_score, best_response_mv) = self.search(
-                        &nboard,
-                        // &hgame,
-                        depth + depth_modifier as usize - 1,
-                        1,
-                        -beta,
-                        -alpha,
-                        kill_time,
-                        Some(vec![&best_response]),
-                        &new_seen_positions,
-                    );
+                    (move_search_score, best_response_mv) =
+                        self.with_position(&nboard, irreversible, |search| {
+                            search.search(
+                                &nboard,
+                                // &hgame,
+                                depth + depth_modifier as usize - 1,
+                                1,
+                                -beta,
+                                -alpha,
+                                kill_time,
+                                Some(best_response),
+                                depth_modifier as usize,
+                                board.piece_on(mv.get_dest()).map(|_| mv.get_dest()),
+                            )
+                        });
                 }
                 alpha += 1;
                 // move_search_score is the score of the best response move from our opponent
                 // invert it; we'll pick the move with the highest score - gives our opponent the worst best response
                 (-move_search_score, best_response_mv)
             };
-            move_values.push((*mv, evaluation));
-            self.search_stats.nodes_searched += 1;
-
-            if kill_time.elapsed() > Duration::ZERO {
-                // the result we got in this search may not be accurate
+            if self.aborted {
+                // this move wasn't fully searched so its score means nothing
                 debug!("Out of time");
-                // we can use this move if it's the first/only one we've looked at
-                // otherwise we discard this result
-                if best_move != default_move {
-                    break;
-                }
+                break;
             }
+            move_values.push((*mv, evaluation));
+            self.search_stats.nodes_searched += 1;
 
             if evaluation > alpha {
                 alpha = evaluation;
@@ -344,7 +897,7 @@ impl CrabChessSearch {
             }
         }
         trace!("");
-        move_values.sort_by(|a, b| b.1.cmp(&a.1));
+        move_values.sort_by_key(|a| std::cmp::Reverse(a.1));
         // trace!("Sored move values: {:#?}", move_values);
         let mut order_moves: Vec<(ChessMove, i32)> = Vec::new();
         // order moves from best to worst
@@ -362,14 +915,16 @@ impl CrabChessSearch {
         }
 
         // alpha is the evaluation of the position since this is the top level search
-        if depth >= self.trans_table_depth_threshold && kill_time.elapsed() == Duration::ZERO {
+        if depth >= self.trans_table_depth_threshold
+            && !self.aborted
+            && !self.near_fifty_move_draw()
+        {
             // Push exact result to transposition table since this is top level node
             self.search_stats.tt_pushed += 1;
             self.transposition_table.insert(
                 board.get_hash(),
                 Transposition {
                     depth,
-                    ply: 0,
                     score: alpha,
                     node_type: NodeType::Exact,
                     best_move,
@@ -380,9 +935,10 @@ impl CrabChessSearch {
         debug!("Finished top level search! evaluation: {}", alpha);
 
         // println!("order_moves: {:?}", order_moves);
-        return (alpha, best_move, order_moves, best_response);
+        (alpha, best_move, order_moves, best_response)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn search(
         &mut self,
         board: &Board,
@@ -392,23 +948,41 @@ impl CrabChessSearch {
         mut alpha: i32,
         beta: i32,
         kill_time: &Instant,
-        suggested_moves: Option<Vec<&ChessMove>>, // try this move first
-        seen_positions: &HashMap<u64, u32>,
+        suggested_move: Option<ChessMove>, // try this move early
+        extensions: usize,                 // how many plies this line has been extended by so far
+        recapture_square: Option<Square>,  // where the previous move captured a piece, if it did
     ) -> (i32, ChessMove) {
         // Search for the best move using alpha-beta pruning
         let default_move = ChessMove::new(Square::A1, Square::A1, None);
+        self.nodes += 1;
+        if self.should_abort(kill_time) {
+            return (0, default_move);
+        }
 
         if board.status() == BoardStatus::Stalemate {
-            return (STALEMATE_SCORE, default_move);
+            return (self.draw_score(board), default_move);
         }
         if board.status() == BoardStatus::Checkmate {
             return (CHECKMATE_SCORE, default_move);
         }
+        if self.position_history.halfmove_clock() >= FIFTY_MOVE_PLIES {
+            // fifty moves without a capture or pawn move
+            return (self.draw_score(board), default_move);
+        }
 
         let mut best_move: ChessMove = default_move;
         let mut best_score = -9999998; // this is distinct from alpha; it may be smaller if no moves are better
 
-        if let Some(transpo) = self.transposition_table.get(board.get_hash(), depth) {
+        // mtd(f) searches the root from here, which has to come back with a real move
+        let is_root = ply == 0;
+
+        // any stored result is good enough to get a move to try first
+        let stored = self.probe(board);
+        let mut hash_move = stored.map(|transpo| transpo.best_move);
+
+        // stored scores don't know how close this position is to a fifty move draw
+        let usable_stored = stored.filter(|_| !self.near_fifty_move_draw());
+        if let Some(transpo) = usable_stored.filter(|transpo| !is_root && transpo.depth >= depth) {
             // get the move from the transposition table
             self.search_stats.tt_hits += 1;
             match transpo.node_type {
@@ -445,43 +1019,289 @@ impl CrabChessSearch {
                 // assumes depth > 0 when this fn is called for the first time
                 // otherwise it will return default_move
                 return (
-                    self.quiescence_search(board, ply + 1, alpha, beta, kill_time, seen_positions),
+                    self.quiescence_search(board, ply + 1, alpha, beta, kill_time),
                     default_move,
                 );
             }
         }
 
+        if hash_move.is_none() {
+            // with a null window this can't be on the principal variation
+            let is_pv_node = beta - alpha > 1;
+            if is_pv_node && depth >= IID_MIN_DEPTH {
+                // a shallower search of this same position should find a decent move to try first
+                self.search_stats.iid_searches += 1;
+                let (_, iid_move) = self.search(
+                    board,
+                    depth - IID_REDUCTION,
+                    ply,
+                    alpha,
+                    beta,
+                    kill_time,
+                    suggested_move,
+                    extensions,
+                    recapture_square,
+                );
+                if self.aborted {
+                    return (0, default_move);
+                }
+                if iid_move != default_move {
+                    hash_move = Some(iid_move);
+                }
+            } else if !is_pv_node && !is_root && depth >= IIR_MIN_DEPTH {
+                // this position hasn't been worth storing yet, so it's probably not worth a full search
+                self.search_stats.internal_reductions += 1;
+                depth -= 1;
+            }
+        }
+
+        let in_check = board.checkers() != &EMPTY;
+        // the static evaluation is only needed near the leaves, and never prunes at the root
+        let static_eval =
+            if !in_check && !is_root && depth < FUTILITY_MARGINS.len().max(RFP_MAX_DEPTH + 1) {
+                Some(self.evaluate(board))
+            } else {
+                None
+            };
+
+        if let Some(eval) = static_eval {
+            // we're so far ahead that our opponent would never let us get here
+            if depth <= RFP_MAX_DEPTH
+                && beta.abs() < MATE_THRESHOLD
+                && eval - RFP_MARGIN * depth as i32 >= beta
+            {
+                self.search_stats.reverse_futility_prunes += 1;
+                return (eval, default_move);
+            }
+            // we're so far behind that only captures could possibly help
+            if depth < RAZOR_MARGINS.len()
+                && alpha.abs() < MATE_THRESHOLD
+                && eval + RAZOR_MARGINS[depth] < alpha
+            {
+                let razor_score =
+                    self.quiescence_search(board, ply + 1, alpha - 1, alpha, kill_time);
+                if self.aborted {
+                    return (0, default_move);
+                }
+                if razor_score < alpha {
+                    self.search_stats.razor_prunes += 1;
+                    return (razor_score, default_move);
+                }
+            }
+        }
+        if !in_check
+            && !is_root
+            && depth >= PROBCUT_MIN_DEPTH
+            && beta - alpha == 1
+            && beta.abs() < MATE_THRESHOLD
+        {
+            if let Some((probcut_score, probcut_move)) = self.probcut(
+                board,
+                beta + PROBCUT_MARGIN,
+                depth,
+                ply,
+                kill_time,
+                extensions,
+            ) {
+                self.search_stats.probcut_prunes += 1;
+                return (probcut_score, probcut_move);
+            }
+            if self.aborted {
+                return (0, default_move);
+            }
+        }
+
+        // at low depth, quiet moves ordered this late are rarely worth looking at
+        let late_move_limit = if !in_check
+            && !is_root
+            && depth < LMP_MOVE_COUNTS.len()
+            && alpha.abs() < MATE_THRESHOLD
+        {
+            Some(LMP_MOVE_COUNTS[depth])
+        } else {
+            None
+        };
+        let mut quiets_tried = 0;
+        // we're so far behind that quiet moves can't possibly catch up to alpha
+        let futility_score = match static_eval {
+            Some(eval) if depth < FUTILITY_MARGINS.len() && alpha.abs() < MATE_THRESHOLD => {
+                Some(eval + FUTILITY_MARGINS[depth]).filter(|score| *score <= alpha)
+            }
+            _ => None,
+        };
+
+        // if the stored move beat beta and nothing else comes close, it's the only move
+        // and deserves a deeper look
+        let singular_extension = match stored {
+            Some(transpo)
+                if depth >= SINGULAR_MIN_DEPTH
+                    && transpo.node_type == NodeType::LowerBound
+                    && transpo.depth + 3 >= depth
+                    && transpo.score.abs() < MATE_THRESHOLD
+                    && extensions < MAX_LINE_EXTENSIONS =>
+            {
+                self.is_singular(
+                    board,
+                    transpo.best_move,
+                    transpo.score - SINGULAR_MARGIN * depth as i32,
+                    depth,
+                    ply,
+                    kill_time,
+                    extensions,
+                )
+            }
+            _ => false,
+        };
+        if self.aborted {
+            return (0, default_move);
+        }
+
         // for future transposition table
         let mut this_node_type = NodeType::UpperBound;
 
-        let movegen: MoveGen = MoveGen::new_legal(&board);
         let mut best_response: ChessMove = default_move;
 
+        // quiet moves that didn't cause a cutoff get penalized if a later move does
+        let mut quiets_searched = [default_move; MAX_QUIETS_TRACKED];
+        let mut num_quiets_searched = 0;
+
         // look at every possible move from this position
-        for (mv, _) in self.get_moves_lazily_ordered(board, movegen, suggested_moves) {
+        let mut move_picker =
+            MovePicker::new(hash_move, self.killer_moves.get(ply), suggested_move);
+        let mut moves_tried = 0;
+        while let Some((mv, stage)) = move_picker.next(board, &self.history) {
+            let mv_index = moves_tried;
+            moves_tried += 1;
+            let is_capture = board.piece_on(mv.get_dest()).is_some();
+            let is_quiet = !is_capture && mv.get_promotion().is_none();
             let nboard = board.make_move_new(mv);
-            // add this position to the map of positions we've seen before
-            let (new_seen_positions, is_draw) =
-                match check_for_draw(seen_positions.clone(), &nboard) {
-                    Ok(m) => (m, false),
-                    Err(_) => (HashMap::new(), true),
-                };
+            let irreversible = is_irreversible(board, mv);
+            let is_draw = self
+                .position_history
+                .is_repetition(nboard.get_hash(), irreversible);
+
+            // look deeper at forcing moves, as long as this line hasn't been extended too much already
+            let extension = if extensions < MAX_LINE_EXTENSIONS
+                && ply < 2 * self.current_search_depth
+                && ((stage == Stage::HashMove && singular_extension)
+                    || nboard.checkers() != &EMPTY
+                    || (is_capture
+                        && Some(mv.get_dest()) == recapture_square
+                        && stage != Stage::BadCaptures)
+                    || is_pawn_push_to_seventh(board, mv))
+            {
+                self.search_stats.extensions += 1;
+                1
+            } else {
+                0
+            };
+            if is_quiet {
+                quiets_tried += 1;
+            }
+            if late_move_limit.is_some_and(|limit| quiets_tried > limit)
+                && stage == Stage::Quiets
+                && extension == 0
+                && nboard.checkers() == &EMPTY
+            {
+                self.search_stats.late_move_prunes += 1;
+                continue;
+            }
+            if let Some(score) = futility_score {
+                // always search at least one move so we have something to return
+                if moves_tried > 1 && is_quiet && extension == 0 && nboard.checkers() == &EMPTY {
+                    self.search_stats.futility_prunes += 1;
+                    best_score = best_score.max(score);
+                    continue;
+                }
+            }
+            let next_recapture_square = if is_capture {
+                Some(mv.get_dest())
+            } else {
+                None
+            };
+
+            // quiet moves that were ordered late probably aren't any good
+            // so look at them with a shallower null window search first
+            // killers and the suggested move are never reduced
+            let reduction = if !is_draw
+                && depth >= LMR_MIN_DEPTH
+                && mv_index >= LMR_MIN_MOVE_INDEX
+                && stage == Stage::Quiets
+                && !in_check
+                && is_quiet
+                && extension == 0
+                && nboard.checkers() == &EMPTY
+            {
+                let mut reduction = self.late_move_reductions[depth.min(63)][mv_index.min(63)];
+                // reduce moves with a good history less and moves with a bad history more
+                let history_score = self.history.get(board.side_to_move(), mv);
+                if history_score > HISTORY_MAX / 2 {
+                    reduction = reduction.saturating_sub(1);
+                } else if history_score < -HISTORY_MAX / 2 {
+                    reduction += 1;
+                }
+                // always leave at least one ply before quiescence search
+                reduction.min(depth - 2)
+            } else {
+                0
+            };
+            if reduction > 0 {
+                let (reduced_score, _) = self.with_position(&nboard, irreversible, |search| {
+                    search.search(
+                        &nboard,
+                        depth - 1 - reduction,
+                        ply + 1,
+                        -alpha - 1,
+                        -alpha,
+                        kill_time,
+                        Some(best_response),
+                        extensions,
+                        next_recapture_square,
+                    )
+                });
+                if self.aborted {
+                    return (0, default_move);
+                }
+                if -reduced_score <= alpha {
+                    // this move failed low even with a reduced search, skip it
+                    self.search_stats.depth_reduction_hits += 1;
+                    self.search_stats.nodes_searched += 1;
+                    if num_quiets_searched < MAX_QUIETS_TRACKED {
+                        quiets_searched[num_quiets_searched] = mv;
+                        num_quiets_searched += 1;
+                    }
+                    if -reduced_score > best_score {
+                        best_score = -reduced_score;
+                        best_move = mv;
+                    }
+                    continue;
+                }
+                // this move looks better than expected, so search it again at full depth
+                self.search_stats.depth_reduction_misses += 1;
+            }
+
             let (move_search_score, sub_response) = if is_draw {
-                (STALEMATE_SCORE, default_move)
+                (self.draw_score(&nboard), default_move)
             } else {
-                self.search(
-                    &nboard,
-                    // &hyp_game,
-                    depth - 1,
-                    ply + 1,
-                    -beta,
-                    -alpha,
-                    kill_time,
-                    Some(vec![&best_response]),
-                    &new_seen_positions,
-                )
+                self.with_position(&nboard, irreversible, |search| {
+                    search.search(
+                        &nboard,
+                        // &hyp_game,
+                        depth - 1 + extension,
+                        ply + 1,
+                        -beta,
+                        -alpha,
+                        kill_time,
+                        Some(best_response),
+                        extensions + extension,
+                        next_recapture_square,
+                    )
+                })
             };
-            // we don't have all the nodes on this tree yet
+            if self.aborted {
+                // we don't have all the nodes on this tree, so nothing here can be trusted
+                return (0, default_move);
+            }
 
             // reduce checkmate moves in subsearches so that sooner mates are more valuable
             let evaluation = if move_search_score <= -999000 {
@@ -494,20 +1314,34 @@ impl CrabChessSearch {
             if evaluation >= beta {
                 // position is too good; opponent would never let us get here
                 // a beta cutoff means we've failed high; this is a lower bound
-                self.search_stats.tt_pushed += 1;
-                self.transposition_table.insert(
-                    board.get_hash(),
-                    Transposition {
-                        depth,
-                        ply,
-                        score: evaluation,
-                        node_type: NodeType::LowerBound,
-                        best_move: mv,
-                    },
-                );
+                if is_quiet {
+                    // remember this move so we try it early in sibling positions
+                    self.killer_moves.store(ply, mv);
+                    let bonus = (depth * depth) as i32;
+                    self.history.update(board.side_to_move(), mv, bonus);
+                    for quiet_mv in quiets_searched.iter().take(num_quiets_searched) {
+                        self.history.update(board.side_to_move(), *quiet_mv, -bonus);
+                    }
+                }
+                if !self.near_fifty_move_draw() {
+                    self.search_stats.tt_pushed += 1;
+                    self.transposition_table.insert(
+                        board.get_hash(),
+                        Transposition {
+                            depth,
+                            score: evaluation,
+                            node_type: NodeType::LowerBound,
+                            best_move: mv,
+                        },
+                    );
+                }
                 return (evaluation, mv);
                 // return (beta, mv);
             }
+            if is_quiet && num_quiets_searched < MAX_QUIETS_TRACKED {
+                quiets_searched[num_quiets_searched] = mv;
+                num_quiets_searched += 1;
+            }
             if evaluation > alpha {
                 alpha = evaluation;
                 best_score = evaluation;
@@ -521,14 +1355,9 @@ impl CrabChessSearch {
                 best_move = mv;
                 best_response = sub_response;
             }
-            // cancel search if we're out of time
-            if kill_time.elapsed() > Duration::ZERO {
-                // trace!("breaking from subsearch at kill time");
-                break;
-            }
         }
 
-        if depth >= self.trans_table_depth_threshold && kill_time.elapsed() == Duration::ZERO {
+        if depth >= self.trans_table_depth_threshold && !self.near_fifty_move_draw() {
             // if no move scores exceeded alpha, this is an upper bound and the true score may be less
             // otherwise it's the true score
             match this_node_type {
@@ -539,7 +1368,6 @@ impl CrabChessSearch {
                         board.get_hash(),
                         Transposition {
                             depth,
-                            ply,
                             score: best_score,
                             node_type: this_node_type,
                             best_move,
@@ -549,7 +1377,111 @@ impl CrabChessSearch {
             }
         }
 
-        return (best_score, best_move);
+        (best_score, best_move)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn probcut(
+        &mut self,
+        board: &Board,
+        probcut_beta: i32,
+        depth: usize,
+        ply: usize,
+        kill_time: &Instant,
+        extensions: usize,
+    ) -> Option<(i32, ChessMove)> {
+        // if a good capture beats beta by a wide margin even in a much shallower search,
+        // a full depth search would almost certainly beat beta too
+        let mut move_picker = MovePicker::new_captures();
+        while let Some((mv, stage)) = move_picker.next(board, &self.history) {
+            if stage != Stage::GoodCaptures {
+                break;
+            }
+            let nboard = board.make_move_new(mv);
+            let score = self.with_position(&nboard, true, |search| {
+                // quiescence search is cheap, so use it to weed out captures first
+                let score = -search.quiescence_search(
+                    &nboard,
+                    ply + 1,
+                    -probcut_beta,
+                    -probcut_beta + 1,
+                    kill_time,
+                );
+                if score < probcut_beta {
+                    return score;
+                }
+                -search
+                    .search(
+                        &nboard,
+                        depth - PROBCUT_REDUCTION,
+                        ply + 1,
+                        -probcut_beta,
+                        -probcut_beta + 1,
+                        kill_time,
+                        None,
+                        extensions,
+                        Some(mv.get_dest()),
+                    )
+                    .0
+            });
+            if self.aborted {
+                return None;
+            }
+            if score >= probcut_beta {
+                return Some((score, mv));
+            }
+        }
+        None
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn is_singular(
+        &mut self,
+        board: &Board,
+        hash_move: ChessMove,
+        singular_beta: i32,
+        depth: usize,
+        ply: usize,
+        kill_time: &Instant,
+        extensions: usize,
+    ) -> bool {
+        // search every move except the hash move at reduced depth with a null window just below
+        // the stored score, if none of them get there then the hash move is singular
+        let mut move_picker = MovePicker::new(Some(hash_move), [None, None], None);
+        while let Some((mv, stage)) = move_picker.next(board, &self.history) {
+            if stage == Stage::HashMove {
+                continue;
+            }
+            let nboard = board.make_move_new(mv);
+            let irreversible = is_irreversible(board, mv);
+            let score = if self
+                .position_history
+                .is_repetition(nboard.get_hash(), irreversible)
+            {
+                self.draw_score(board)
+            } else {
+                -self
+                    .with_position(&nboard, irreversible, |search| {
+                        search.search(
+                            &nboard,
+                            (depth - 1) / 2,
+                            ply + 1,
+                            -singular_beta,
+                            -singular_beta + 1,
+                            kill_time,
+                            None,
+                            extensions,
+                            None,
+                        )
+                    })
+                    .0
+            };
+            if self.aborted || score >= singular_beta {
+                return false;
+            }
+        }
+        self.search_stats.singular_extensions += 1;
+        true
     }
 
     fn quiescence_search(
@@ -559,20 +1491,40 @@ impl CrabChessSearch {
         mut alpha: i32,
         beta: i32,
         kill_time: &Instant,
-        seen_positions: &HashMap<u64, u32>,
     ) -> i32 {
+        self.nodes += 1;
+        if self.should_abort(kill_time) {
+            return 0;
+        }
         if ply > self.search_stats.max_ply {
             self.search_stats.max_ply = ply
         }
+
+        // any stored result is deep enough for quiescence search
+        // unless the score should be shrinking towards a fifty move draw
+        let stored = self.probe(board).filter(|_| !self.near_fifty_move_draw());
+        if let Some(transpo) = stored {
+            let usable = match transpo.node_type {
+                NodeType::Exact => true,
+                NodeType::LowerBound => transpo.score >= beta,
+                NodeType::UpperBound => transpo.score <= alpha,
+            };
+            if usable {
+                self.search_stats.qsearch_tt_hits += 1;
+                return transpo.score;
+            }
+        }
+        let original_alpha = alpha;
+
         // filter targets
         let targets = board.color_combined(!board.side_to_move());
-        let mut movegen: MoveGen = MoveGen::new_legal(&board);
+        let mut movegen: MoveGen = MoveGen::new_legal(board);
         movegen.set_iterator_mask(*targets);
         if movegen.len() == 0 {
             if board.status() == BoardStatus::Checkmate {
                 return CHECKMATE_SCORE;
             } else if board.status() == BoardStatus::Stalemate {
-                return STALEMATE_SCORE;
+                return self.draw_score(board);
             }
             // no attacking moves -> evaluate the board
         }
@@ -582,10 +1534,10 @@ impl CrabChessSearch {
         let forced_move = board.checkers().popcnt() != 0;
 
         // if the move isn't forced the player need not make it
-        let evaluation = crab_evaluate::evaluate_material(board);
-        self.search_stats.boards_evaluated += 1;
+        let evaluation = self.evaluate(board);
 
         let mut best_eval = -99999999;
+        let mut best_move = ChessMove::new(Square::A1, Square::A1, None);
 
         if !forced_move {
             // if this move isn't forced, then we don't have to capture anything
@@ -604,7 +1556,8 @@ impl CrabChessSearch {
             return evaluation;
         }
 
-        for (mv, _) in self.get_moves_lazily_ordered(board, movegen, None) {
+        let mut move_picker = MovePicker::new_captures();
+        while let Some((mv, stage)) = move_picker.next(board, &self.history) {
             // Evaluate this move if ANY of these conditions is true
             // (1) this move captures a piece
             // (2) this move is a promotion
@@ -634,34 +1587,42 @@ impl CrabChessSearch {
                     return best_eval.max(evaluation);
                 }
             }
-            // check draw by repetition
-            let (new_seen_positions, is_draw) =
-                match check_for_draw(seen_positions.clone(), &nboard) {
-                    Ok(m) => (m, false),
-                    Err(_) => (HashMap::new(), true),
-                };
-
-            let move_search_score = if is_draw {
-                0
-            } else {
-                self.quiescence_search(
-                    &nboard,
-                    ply + 1,
-                    -beta,
-                    -alpha,
-                    kill_time,
-                    &new_seen_positions,
-                )
-            };
+            // don't bother with quiet captures that lose material in the exchange
+            if !forced_move
+                && nboard.checkers() == &EMPTY
+                && mv.get_promotion().is_none()
+                && stage == Stage::BadCaptures
+            {
+                self.search_stats.see_prunes += 1;
+                continue;
+            }
+            // even winning this piece for free wouldn't be enough to catch up
+            if !forced_move && nboard.checkers() == &EMPTY && mv.get_promotion().is_none() {
+                if let Some(victim) = board.piece_on(mv.get_dest()) {
+                    if evaluation + crab_evaluate::piece_value(victim) + DELTA_MARGIN < alpha {
+                        self.search_stats.delta_prunes += 1;
+                        continue;
+                    }
+                }
+            }
+            // every move here is a capture, so there's no need to check for repetition
+            let move_search_score = self.with_position(&nboard, true, |search| {
+                search.quiescence_search(&nboard, ply + 1, -beta, -alpha, kill_time)
+            });
+            if self.aborted {
+                return 0;
+            }
             let score = -move_search_score;
             self.search_stats.nodes_searched += 1;
             if score >= beta {
                 // opponent would never let us get here
                 // return beta;
+                self.store_quiescence_result(board, stored, score, NodeType::LowerBound, mv);
                 return score;
             }
             if score > best_eval {
                 best_eval = score;
+                best_move = mv;
             }
             if score > alpha {
                 // wow a great result!
@@ -669,20 +1630,119 @@ impl CrabChessSearch {
             }
         }
 
-        return best_eval.max(evaluation);
+        let node_type = if alpha > original_alpha {
+            NodeType::Exact
+        } else {
+            NodeType::UpperBound
+        };
+        self.store_quiescence_result(
+            board,
+            stored,
+            best_eval.max(evaluation),
+            node_type,
+            best_move,
+        );
+        best_eval.max(evaluation)
         // return alpha;
     }
+
+    fn store_quiescence_result(
+        &mut self,
+        board: &Board,
+        stored: Option<Transposition>,
+        score: i32,
+        node_type: NodeType,
+        best_move: ChessMove,
+    ) {
+        // never replace a result from the main search with a quiescence search result
+        if stored.is_some_and(|transpo| transpo.depth > 0) || self.near_fifty_move_draw() {
+            return;
+        }
+        self.search_stats.tt_pushed += 1;
+        self.transposition_table.insert(
+            board.get_hash(),
+            Transposition {
+                depth: 0,
+                score,
+                node_type,
+                best_move,
+            },
+        );
+    }
+}
+
+fn is_pawn_push_to_seventh(board: &Board, mv: ChessMove) -> bool {
+    // a pawn one step away from promotion is worth a closer look
+    if board.piece_on(mv.get_source()) != Some(Piece::Pawn) {
+        return false;
+    }
+    match board.side_to_move() {
+        Color::White => mv.get_dest().get_rank() == Rank::Seventh,
+        Color::Black => mv.get_dest().get_rank() == Rank::Second,
+    }
 }
 
-fn check_for_draw(
-    mut seen_positions: HashMap<u64, u32>,
-    board: &Board,
-) -> Result<HashMap<u64, u32>, ()> {
-    let value = seen_positions.entry(board.get_hash()).or_insert(0);
-    *value += 1;
-    if *value >= 3 {
-        Err(())
-    } else {
-        Ok(seen_positions)
+fn pick_varied_move(
+    move_values: &[(ChessMove, i32)],
+    margin: i32,
+    rng: &mut StdRng,
+) -> Option<ChessMove> {
+    // any move within the margin of the best score is equally likely
+    let best_score = move_values.iter().map(|(_, score)| *score).max()?;
+    if best_score.abs() >= MATE_THRESHOLD {
+        return None;
+    }
+    move_values
+        .iter()
+        .filter(|(_, score)| *score + margin >= best_score)
+        .map(|(mv, _)| *mv)
+        .choose(rng)
+}
+
+fn calculate_late_move_reductions() -> [[usize; 64]; 64] {
+    // reductions grow logarithmically with both remaining depth and move index
+    let mut reductions = [[0usize; 64]; 64];
+    for (depth, depth_reductions) in reductions.iter_mut().enumerate().skip(1) {
+        for (mv_index, reduction) in depth_reductions.iter_mut().enumerate().skip(1) {
+            *reduction =
+                ((depth as f64).ln() * (mv_index as f64).ln() / LMR_DIVISOR).floor() as usize;
+        }
+    }
+    reductions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn deterministic_search(search: &mut CrabChessSearch, fen: &str) -> (i32, ChessMove, i32) {
+        let board = Board::from_str(fen).unwrap();
+        let game = Game::new_with_board(board);
+        search.set_game_start(GameStart {
+            board,
+            halfmove_clock: 0,
+        });
+        search.cum_search_stats = SearchStats::default();
+        let (score, mv) = search.iterative_search_deepening(&board, &game, 6, Duration::ZERO);
+        (score, mv, search.cum_search_stats.nodes_searched)
+    }
+
+    #[test]
+    fn deterministic_searches_repeat() {
+        // same move, score and node count every time, including the random skill pick
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+        let mut search = CrabChessSearch::new();
+        search.set_deterministic(true);
+        search.set_node_budget(Some(20_000));
+        search.set_skill(Skill::from_level(5));
+        let first = deterministic_search(&mut search, fen);
+        assert_eq!(deterministic_search(&mut search, fen), first);
+
+        let mut other_search = CrabChessSearch::new();
+        other_search.set_deterministic(true);
+        other_search.set_node_budget(Some(20_000));
+        other_search.set_skill(Skill::from_level(5));
+        assert_eq!(deterministic_search(&mut other_search, fen), first);
     }
 }
@@ -0,0 +1,107 @@
+use crate::crab_evaluate::piece_value;
+use chess::Piece::{Bishop, King, Knight, Pawn, Queen, Rook};
+use chess::{BitBoard, Board, ChessMove, Color, Piece, Square, EMPTY};
+
+// the king is worth more than everything else together, so it only recaptures last
+const SEE_KING_VALUE: i32 = 20000;
+
+// pieces are tried as recapturers from least to most valuable
+const RECAPTURE_ORDER: [Piece; 6] = [Pawn, Knight, Bishop, Rook, Queen, King];
+
+fn see_value(piece: Piece) -> i32 {
+    match piece {
+        King => SEE_KING_VALUE,
+        _ => piece_value(piece),
+    }
+}
+
+fn attackers_to(board: &Board, square: Square, occupied: BitBoard) -> BitBoard {
+    // every piece of either color that attacks this square given this occupancy
+    // recalculating sliders with a smaller occupancy is what finds x-rays
+    let bishops_queens = board.pieces(Bishop) | board.pieces(Queen);
+    let rooks_queens = board.pieces(Rook) | board.pieces(Queen);
+    let white_pawns = board.pieces(Pawn) & board.color_combined(Color::White);
+    let black_pawns = board.pieces(Pawn) & board.color_combined(Color::Black);
+    (chess::get_pawn_attacks(square, Color::Black, white_pawns)
+        | chess::get_pawn_attacks(square, Color::White, black_pawns)
+        | (chess::get_knight_moves(square) & board.pieces(Knight))
+        | (chess::get_king_moves(square) & board.pieces(King))
+        | (chess::get_bishop_moves(square, occupied) & bishops_queens)
+        | (chess::get_rook_moves(square, occupied) & rooks_queens))
+        & occupied
+}
+
+pub fn static_exchange_evaluation(board: &Board, mv: ChessMove) -> i32 {
+    // Returns the material the side to move should expect to gain from this move
+    // if both sides keep recapturing on the destination square with their cheapest piece
+    // and either side may stop recapturing whenever it would lose material
+    let source = mv.get_source();
+    let target = mv.get_dest();
+    let Some(mut piece_on_target) = board.piece_on(source) else {
+        return 0;
+    };
+    let mut occupied = *board.combined() ^ BitBoard::from_square(source);
+
+    let mut gain = [0i32; 32];
+    gain[0] = match board.piece_on(target) {
+        Some(victim) => see_value(victim),
+        None => {
+            if piece_on_target == Pawn && source.get_file() != target.get_file() {
+                // en passant, the captured pawn isn't on the target square
+                if let Some(ep_square) = board.en_passant() {
+                    occupied ^= BitBoard::from_square(ep_square);
+                }
+                see_value(Pawn)
+            } else {
+                0
+            }
+        }
+    };
+    if let Some(promotion) = mv.get_promotion() {
+        gain[0] += see_value(promotion) - see_value(Pawn);
+        piece_on_target = promotion;
+    }
+
+    let mut side = !board.side_to_move();
+    let mut depth = 0;
+    loop {
+        let attackers = attackers_to(board, target, occupied) & board.color_combined(side);
+        if attackers == EMPTY {
+            break;
+        }
+        let Some((recapturer, from_bb)) = RECAPTURE_ORDER.iter().find_map(|piece| {
+            let candidates = attackers & board.pieces(*piece);
+            if candidates == EMPTY {
+                None
+            } else {
+                Some((*piece, BitBoard::from_square(candidates.to_square())))
+            }
+        }) else {
+            break;
+        };
+        if recapturer == King
+            && attackers_to(board, target, occupied ^ from_bb) & board.color_combined(!side)
+                != EMPTY
+        {
+            // the king can't recapture onto a defended square
+            break;
+        }
+
+        if depth + 1 >= gain.len() {
+            break;
+        }
+        depth += 1;
+        // score for the side making this recapture if the exchange stopped here
+        gain[depth] = see_value(piece_on_target) - gain[depth - 1];
+        occupied ^= from_bb;
+        piece_on_target = recapturer;
+        side = !side;
+    }
+
+    // each side picks the better of stopping or continuing the exchange
+    while depth > 0 {
+        gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+        depth -= 1;
+    }
+    gain[0]
+}
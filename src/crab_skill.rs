@@ -0,0 +1,97 @@
+use chess::ChessMove;
+use rand::Rng;
+
+// skill level 20 is full strength
+pub const MAX_SKILL_LEVEL: u32 = 20;
+// rating of every skill level, fitted to self-play matches between the levels with full strength
+// pinned at the rating TOURNAMENT.md gives the engine against stockfish, see the notes there
+const LEVEL_ELO: [u32; MAX_SKILL_LEVEL as usize + 1] = [
+    400, 590, 750, 900, 1030, 1140, 1240, 1330, 1400, 1470, 1520, 1570, 1610, 1650, 1680, 1720,
+    1750, 1780, 1820, 1860, 1900,
+];
+pub const MIN_ELO: u32 = LEVEL_ELO[0];
+pub const MAX_ELO: u32 = LEVEL_ELO[MAX_SKILL_LEVEL as usize];
+
+// the weakest skill level searches this many nodes, every two levels doubles it
+const MIN_NODE_LIMIT: u64 = 1000;
+// noise never makes a move look more than this much better than it is
+const MAX_NOISE: i32 = 100;
+// only the best few root moves are ever considered
+const CANDIDATE_MOVES: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Skill {
+    level: u32,
+}
+
+impl Default for Skill {
+    fn default() -> Self {
+        Self::from_level(MAX_SKILL_LEVEL)
+    }
+}
+
+impl Skill {
+    pub fn from_level(level: u32) -> Self {
+        Self {
+            level: level.min(MAX_SKILL_LEVEL),
+        }
+    }
+
+    pub fn from_elo(elo: u32) -> Self {
+        // the strongest level that isn't rated above elo
+        let level = LEVEL_ELO
+            .iter()
+            .rposition(|rating| *rating <= elo)
+            .unwrap_or(0);
+        Self::from_level(level as u32)
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.level < MAX_SKILL_LEVEL
+    }
+
+    pub fn max_depth(&self) -> Option<usize> {
+        // level 0 looks 2 plies ahead, level 19 looks 11 plies ahead
+        self.is_limited().then_some(2 + self.level as usize / 2)
+    }
+
+    pub fn node_limit(&self) -> Option<u64> {
+        self.is_limited()
+            .then_some(MIN_NODE_LIMIT << (self.level / 2))
+    }
+
+    fn weakness(&self) -> i32 {
+        120 - 2 * self.level as i32
+    }
+
+    pub fn max_loss(&self) -> Option<i32> {
+        // the furthest behind the best move a move can be and still get picked,
+        // so moves any worse than this don't need exact scores
+        let weakness = self.weakness();
+        self.is_limited()
+            .then_some(MAX_NOISE * (weakness - 1) / (128 - weakness) + 1)
+    }
+
+    pub fn pick_move(
+        &self,
+        move_values: &[(ChessMove, i32)],
+        rng: &mut impl Rng,
+    ) -> Option<ChessMove> {
+        // add random noise to every score, weaker levels get more noise and care less
+        // about how far behind the best move another move is
+        // move_values are sorted from best to worst
+        let candidates = &move_values[..move_values.len().min(CANDIDATE_MOVES)];
+        let best_score = candidates.first()?.1;
+        let worst_score = candidates.last()?.1;
+        let weakness = self.weakness();
+        let spread = (best_score - worst_score).min(MAX_NOISE);
+        candidates
+            .iter()
+            .max_by_key(|(_, score)| {
+                let push =
+                    (weakness * (best_score - score) + spread * rng.gen_range(0..weakness)) / 128;
+                score + push
+            })
+            .map(|(mv, _)| *mv)
+    }
+}
@@ -1,6 +1,25 @@
-use chess::{ChessMove, Square};
+use crate::crab_search::{CHECKMATE_SCORE, MATE_THRESHOLD};
+use chess::{ChessMove, Piece, ALL_SQUARES, PROMOTION_PIECES};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 
-#[derive(Clone, Copy, PartialEq, PartialOrd)]
+// entries that share a bucket, 8 entries of 8 bytes fill a 64 byte cache line
+const BUCKET_SIZE: usize = 8;
+// searches are numbered mod this, so entries know how many moves ago they were stored
+const GENERATIONS: u8 = 64;
+// when picking an entry to replace, being one search older counts as this many plies shallower
+const AGE_WEIGHT: i32 = 8;
+// an entry for the same position is kept if it's this much deeper than the new result
+const SAME_POSITION_DEPTH_MARGIN: usize = 2;
+
+// scores are squeezed into 16 bits - mate scores go at the very top and bottom,
+// a mate right now is i16::MAX and every ply further away is one less
+const PACKED_MATE: i32 = i16::MAX as i32;
+const MATE_SCORE: i32 = -CHECKMATE_SCORE;
+const MAX_MATE_DISTANCE: i32 = MATE_SCORE - MATE_THRESHOLD;
+// anything else has to fit below the mate scores
+const MAX_PACKED_SCORE: i32 = PACKED_MATE - MAX_MATE_DISTANCE - 1;
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
 
 pub enum NodeType {
     UpperBound,
@@ -8,50 +27,348 @@ pub enum NodeType {
     LowerBound,
 }
 
-#[derive(Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
 pub struct Transposition {
     pub depth: usize,
-    pub ply: usize,
     pub score: i32,
     pub node_type: NodeType,
     pub best_move: ChessMove,
 }
 
 impl Transposition {
-    fn empty() -> Self {
+    // packed into a u64 so that it can be written and read atomically by every search thread
+    // bits 0-5 move source, 6-11 move destination, 12-14 promotion piece, 16-31 score,
+    // 32-39 depth, 40-41 node type, 42-47 generation, 48-63 the top of the key
+    fn pack(&self, key: u64, generation: u8) -> u64 {
+        let promotion = match self.best_move.get_promotion() {
+            Some(piece) => 1 + PROMOTION_PIECES.iter().position(|p| *p == piece).unwrap() as u64,
+            None => 0,
+        };
+        let node_type = match self.node_type {
+            NodeType::UpperBound => 0,
+            NodeType::Exact => 1,
+            NodeType::LowerBound => 2,
+        };
+        (self.best_move.get_source().to_index() as u64)
+            | (self.best_move.get_dest().to_index() as u64) << 6
+            | promotion << 12
+            | (pack_score(self.score) as u16 as u64) << 16
+            | (self.depth.min(255) as u64) << 32
+            | node_type << 40
+            | ((generation % GENERATIONS) as u64) << 42
+            | key_fragment(key) << 48
+    }
+
+    fn unpack(data: u64) -> Self {
+        let promotion: Option<Piece> = match (data >> 12) & 0b111 {
+            0 => None,
+            n => Some(PROMOTION_PIECES[n as usize - 1]),
+        };
         Self {
-            depth: 0,
-            ply: 0,
-            score: 0,
-            node_type: NodeType::Exact,
-            best_move: ChessMove::new(Square::A1, Square::A1, None),
+            depth: ((data >> 32) & 0xff) as usize,
+            score: unpack_score((data >> 16) as u16 as i16),
+            node_type: match (data >> 40) & 0b11 {
+                0 => NodeType::UpperBound,
+                1 => NodeType::Exact,
+                _ => NodeType::LowerBound,
+            },
+            best_move: ChessMove::new(
+                ALL_SQUARES[(data & 0x3f) as usize],
+                ALL_SQUARES[((data >> 6) & 0x3f) as usize],
+                promotion,
+            ),
         }
     }
 }
 
-pub struct TranspositionTable(chess::CacheTable<Transposition>);
+fn pack_score(score: i32) -> i16 {
+    // scores too big to be mates, like the ones bounds start out at, become the nearest mate,
+    // which as a bound is still true
+    let packed = if score.abs() >= MATE_THRESHOLD {
+        let distance = (MATE_SCORE - score.abs()).clamp(0, MAX_MATE_DISTANCE);
+        score.signum() * (PACKED_MATE - distance)
+    } else {
+        score.clamp(-MAX_PACKED_SCORE, MAX_PACKED_SCORE)
+    };
+    packed as i16
+}
+
+fn unpack_score(packed: i16) -> i32 {
+    let packed = packed as i32;
+    if packed.abs() > MAX_PACKED_SCORE {
+        packed.signum() * (MATE_SCORE - (PACKED_MATE - packed.abs()))
+    } else {
+        packed
+    }
+}
+
+fn key_fragment(key: u64) -> u64 {
+    // the bottom of the key picks the bucket, the top is stored to tell positions in it apart
+    key >> 48
+}
+
+fn key_fragment_of(data: u64) -> u64 {
+    data >> 48
+}
+
+fn generation_of(data: u64) -> u8 {
+    ((data >> 42) & (GENERATIONS as u64 - 1)) as u8
+}
+
+// every entry is a single u64, so threads writing to the same entry at once can't tear it
+#[derive(Default)]
+#[repr(align(64))]
+struct Bucket([AtomicU64; BUCKET_SIZE]);
+
+pub struct TranspositionTable {
+    buckets: Vec<Bucket>,
+    // bumped before every search so entries from earlier moves get replaced first
+    generation: AtomicU8,
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl TranspositionTable {
     pub fn new() -> Self {
-        Self(
-            // 2^18 is 262,144
-            // at ~40b each that's around 10.5 megabytes
-            chess::CacheTable::new(1 << 19, Transposition::empty()),
-        )
+        // 2^18 buckets of 8 is 2,097,152 entries
+        // at 8b each that's around 16.8 megabytes
+        Self {
+            buckets: (0..1 << 18).map(|_| Bucket::default()).collect(),
+            generation: AtomicU8::new(0),
+        }
+    }
+
+    pub fn clear(&self) {
+        for entry in self.buckets.iter().flat_map(|bucket| bucket.0.iter()) {
+            entry.store(0, Ordering::Relaxed);
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    pub fn new_search(&self) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        self.generation
+            .store((generation + 1) % GENERATIONS, Ordering::Relaxed);
+    }
+
+    fn bucket(&self, key: u64) -> &Bucket {
+        &self.buckets[(key as usize) & (self.buckets.len() - 1)]
+    }
+
+    fn find(&self, key: u64) -> Option<(&AtomicU64, u64)> {
+        // the entry holding this position and what's in it, read once so it can't change under us
+        self.bucket(key).0.iter().find_map(|entry| {
+            let data = entry.load(Ordering::Relaxed);
+            (data != 0 && key_fragment_of(data) == key_fragment(key)).then_some((entry, data))
+        })
+    }
+
+    fn replacement_value(data: u64, generation: u8) -> i32 {
+        // the entry with the lowest value in a bucket is the one that gets replaced
+        if data == 0 {
+            // empty
+            return i32::MIN;
+        }
+        let transposition = Transposition::unpack(data);
+        let age = generation.wrapping_sub(generation_of(data)) % GENERATIONS;
+        let node_type_bonus = match transposition.node_type {
+            NodeType::Exact => 2,
+            NodeType::LowerBound => 1,
+            NodeType::UpperBound => 0,
+        };
+        transposition.depth as i32 - AGE_WEIGHT * age as i32 + node_type_bonus
+    }
+
+    pub fn insert(&self, key: u64, value: Transposition) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let entry = match self.find(key) {
+            Some((entry, data)) => {
+                // don't throw away a much deeper search of this position from this same search
+                let stored = Transposition::unpack(data);
+                if value.node_type != NodeType::Exact
+                    && value.depth + SAME_POSITION_DEPTH_MARGIN < stored.depth
+                    && generation_of(data) == generation
+                {
+                    return;
+                }
+                entry
+            }
+            None => self
+                .bucket(key)
+                .0
+                .iter()
+                .min_by_key(|entry| {
+                    Self::replacement_value(entry.load(Ordering::Relaxed), generation)
+                })
+                .unwrap(),
+        };
+        entry.store(value.pack(key, generation), Ordering::Relaxed);
+    }
+
+    pub fn get(&self, key: u64) -> Option<Transposition> {
+        // whatever is stored, however shallow - callers decide if the score is deep enough,
+        // and the move is worth trying first either way
+        let generation = self.generation.load(Ordering::Relaxed);
+        let (entry, data) = self.find(key)?;
+        let transposition = Transposition::unpack(data);
+        if generation_of(data) != generation {
+            // still useful, so it shouldn't be the first thing replaced
+            // unless another thread has written to this slot since we read it
+            let _ = entry.compare_exchange(
+                data,
+                transposition.pack(key, generation),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+        }
+        Some(transposition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess::{Square, ALL_SQUARES};
+
+    fn transposition(best_move: ChessMove, score: i32, depth: usize) -> Transposition {
+        Transposition {
+            depth,
+            score,
+            node_type: NodeType::Exact,
+            best_move,
+        }
     }
 
-    pub fn insert(&mut self, key: u64, value: Transposition) {
-        self.0.add(key, value);
+    fn round_trip(transposition: Transposition) -> Transposition {
+        Transposition::unpack(transposition.pack(0x1234_5678_9abc_def0, 5))
     }
 
-    pub fn get(&self, key: u64, depth: usize) -> Option<Transposition> {
-        if let Some(val) = self.0.get(key) {
-            if val.depth < depth {
-                return None;
+    fn e2e4() -> ChessMove {
+        ChessMove::new(Square::E2, Square::E4, None)
+    }
+
+    #[test]
+    fn moves_round_trip() {
+        for source in ALL_SQUARES {
+            for dest in ALL_SQUARES {
+                let mv = ChessMove::new(source, dest, None);
+                assert_eq!(round_trip(transposition(mv, 0, 1)).best_move, mv);
             }
-        } else {
-            return None;
         }
-        self.0.get(key)
+        for piece in PROMOTION_PIECES {
+            let mv = ChessMove::new(Square::B7, Square::A8, Some(piece));
+            assert_eq!(round_trip(transposition(mv, 0, 1)).best_move, mv);
+        }
+    }
+
+    #[test]
+    fn scores_round_trip() {
+        for score in [
+            0,
+            1,
+            -1,
+            35,
+            -250,
+            1500,
+            -9000,
+            MAX_PACKED_SCORE,
+            -MAX_PACKED_SCORE,
+        ] {
+            assert_eq!(round_trip(transposition(e2e4(), score, 1)).score, score);
+        }
+    }
+
+    #[test]
+    fn mate_scores_round_trip() {
+        for distance in 0..=MAX_MATE_DISTANCE {
+            let mated = CHECKMATE_SCORE + distance;
+            assert_eq!(round_trip(transposition(e2e4(), mated, 1)).score, mated);
+            assert_eq!(round_trip(transposition(e2e4(), -mated, 1)).score, -mated);
+        }
+    }
+
+    #[test]
+    fn scores_out_of_range_stay_true_as_bounds() {
+        // big non-mate scores are clamped, anything past a mate becomes that mate
+        assert_eq!(
+            round_trip(transposition(e2e4(), 500000, 1)).score,
+            MAX_PACKED_SCORE
+        );
+        assert_eq!(
+            round_trip(transposition(e2e4(), -500000, 1)).score,
+            -MAX_PACKED_SCORE
+        );
+        assert_eq!(
+            round_trip(transposition(e2e4(), -9999998, 1)).score,
+            CHECKMATE_SCORE
+        );
+        assert_eq!(
+            round_trip(transposition(e2e4(), 999999777, 1)).score,
+            MATE_SCORE
+        );
+    }
+
+    #[test]
+    fn depths_round_trip() {
+        for depth in 0..=255 {
+            assert_eq!(round_trip(transposition(e2e4(), 0, depth)).depth, depth);
+        }
+        assert_eq!(round_trip(transposition(e2e4(), 0, 300)).depth, 255);
+    }
+
+    #[test]
+    fn node_types_round_trip() {
+        for node_type in [NodeType::UpperBound, NodeType::Exact, NodeType::LowerBound] {
+            let stored = Transposition {
+                node_type,
+                ..transposition(e2e4(), -42, 7)
+            };
+            assert_eq!(round_trip(stored), stored);
+        }
+    }
+
+    #[test]
+    fn generations_round_trip() {
+        for generation in 0..GENERATIONS {
+            let data = transposition(e2e4(), 0, 1).pack(0, generation);
+            assert_eq!(generation_of(data), generation);
+        }
+    }
+
+    #[test]
+    fn key_fragments_round_trip() {
+        for key in [0, 1, 0xffff_0000_0000_0000, 0x8000_0000_0000_0001, u64::MAX] {
+            let data = transposition(e2e4(), 0, 1).pack(key, 0);
+            assert_eq!(key_fragment_of(data), key_fragment(key));
+        }
+    }
+
+    #[test]
+    fn fields_dont_overlap() {
+        // every field at its largest next to every other field at its largest
+        let stored = Transposition {
+            depth: 255,
+            score: -MATE_SCORE,
+            node_type: NodeType::LowerBound,
+            best_move: ChessMove::new(Square::H8, Square::H8, Some(Piece::Queen)),
+        };
+        let data = stored.pack(u64::MAX, GENERATIONS - 1);
+        assert_eq!(Transposition::unpack(data), stored);
+        assert_eq!(generation_of(data), GENERATIONS - 1);
+        assert_eq!(key_fragment_of(data), 0xffff);
+    }
+
+    #[test]
+    fn table_keeps_entries() {
+        let table = TranspositionTable::new();
+        let key = 0xdead_beef_0000_0042;
+        assert!(table.get(key).is_none());
+        let stored = transposition(e2e4(), 123, 6);
+        table.insert(key, stored);
+        assert_eq!(table.get(key), Some(stored));
     }
 }
@@ -1,15 +1,27 @@
-use crate::crab_search::CrabChessSearch;
+use crate::crab_mate::{MateResult, MateSolver};
+use crate::crab_mcts::CrabMcts;
+use crate::crab_problem::{ProblemKind, ProblemSolver};
+use crate::crab_repetition::GameStart;
+use crate::crab_search::{CrabChessSearch, SearchDriver};
+use crate::crab_skill::{Skill, MAX_ELO, MAX_SKILL_LEVEL, MIN_ELO};
 use crate::speval::SinglePlayerEvaluator;
 use chess::Color::{Black, White};
-use chess::{Board, Game};
+use chess::{Board, ChessMove, Game};
 use log::{debug, error, info, warn};
 use std::env;
 use std::io::{self, BufRead};
 use std::str::FromStr;
-use std::time::Duration;
-use vampirc_uci::{parse_one, UciMessage, UciSearchControl};
+use std::time::{Duration, Instant};
+use vampirc_uci::{parse_one, UciMessage};
 pub mod crab_evaluate;
+pub mod crab_mate;
+pub mod crab_mcts;
+pub mod crab_ordering;
+pub mod crab_problem;
+pub mod crab_repetition;
 pub mod crab_search;
+pub mod crab_see;
+pub mod crab_skill;
 pub mod crab_transposition;
 pub mod precomputed;
 pub mod speval;
@@ -34,8 +46,91 @@ fn program_file_name() -> String {
     }
 }
 
+// roughly our lichess rating, for guessing how much to avoid draws against an opponent
+const ESTIMATED_RATING: i32 = 1900;
+// contempt in centipawns for every 100 rating points we have over our opponent
+const CONTEMPT_PER_100_RATING: i32 = 10;
+const MAX_CONTEMPT: i32 = 100;
+// opening variety, how far behind the best move a move can be and still get played
+const MAX_VARIETY: i32 = 200;
+const DEFAULT_VARIETY_MOVES: usize = 8;
+
+fn contempt_from_opponent(opponent: &str) -> Option<i32> {
+    // UCI_Opponent looks like "GM 2800 human Magnus Carlsen" or "none none computer crab"
+    let rating: i32 = opponent.split_whitespace().nth(1)?.parse().ok()?;
+    Some(
+        ((ESTIMATED_RATING - rating) * CONTEMPT_PER_100_RATING / 100)
+            .clamp(-MAX_CONTEMPT, MAX_CONTEMPT),
+    )
+}
+
+fn print_uci_options() {
+    // tell the gui which options we support, then that we're ready
+    println!("option name Threads type spin default 1 min 1 max 64");
+    println!("option name Contempt type spin default 0 min -{MAX_CONTEMPT} max {MAX_CONTEMPT}");
+    println!("option name UCI_Opponent type string default <empty>");
+    println!(
+        "option name Skill Level type spin default {MAX_SKILL_LEVEL} min 0 max {MAX_SKILL_LEVEL}"
+    );
+    println!("option name UCI_LimitStrength type check default false");
+    println!("option name UCI_Elo type spin default {MAX_ELO} min {MIN_ELO} max {MAX_ELO}");
+    println!("option name Variety type spin default 0 min 0 max {MAX_VARIETY}");
+    println!("option name Variety Moves type spin default {DEFAULT_VARIETY_MOVES} min 0 max 100");
+    println!(
+        "option name Seed type spin default 0 min 0 max {}",
+        i32::MAX
+    );
+    println!("option name Deterministic type check default false");
+    println!("option name Driver type combo default AlphaBeta var AlphaBeta var MTDf");
+    println!("option name Mode type combo default Search var Search var MCTS");
+    println!("uciok");
+}
+
+fn solve_problem(kind: ProblemKind, args: &[String]) -> Result<(), ()> {
+    // crab helpmate 2 <fen> or crab selfmate 2 <fen>
+    let usage = || {
+        println!(
+            "Usage: {} helpmate|selfmate <moves> <fen>",
+            program_file_name()
+        )
+    };
+    // a problem in 0 moves has no solution to find
+    let Some(Ok(moves @ 1..)) = args.first().map(|moves| moves.parse::<usize>()) else {
+        usage();
+        return Err(());
+    };
+    let fen = args[1..].join(" ");
+    let Ok(board) = Board::from_str(&fen) else {
+        println!("Couldn't read the position {fen}");
+        usage();
+        return Err(());
+    };
+    let start_time = Instant::now();
+    let mut solver = ProblemSolver::new(kind);
+    let solutions = solver.solve(&board, moves);
+    for line in &solutions {
+        let moves: Vec<String> = line.iter().map(|mv| mv.to_string()).collect();
+        println!("{}", moves.join(" "));
+    }
+    println!(
+        "{} solution(s) to {:?} in {} after {} nodes - {:?}",
+        solutions.len(),
+        kind,
+        moves,
+        solver.nodes(),
+        start_time.elapsed()
+    );
+    Ok(())
+}
+
 fn main() -> Result<(), ()> {
     let args: Vec<String> = env::args().collect();
+    // chess problems are solved straight from the command line
+    match args.get(1).map(|arg| arg.to_lowercase()).as_deref() {
+        Some("helpmate") => return solve_problem(ProblemKind::Helpmate, &args[2..]),
+        Some("selfmate") => return solve_problem(ProblemKind::Selfmate, &args[2..]),
+        _ => (),
+    }
     // human color provided by first argument, otherwise AI vs AI
     let (log_enabled, log_level) = if args.len() > 1 {
         match args[1].to_lowercase().as_str() {
@@ -78,13 +173,30 @@ fn main() -> Result<(), ()> {
             .expect("Failed to read line");
         let input = input.trim(); // Remove the trailing newline character
 
+        // bot_vs_bot games take an optional seed at the end for their random choices, but moves are
+        // still timed, so the same seed can give a different game
+        let (input, seed) = match input.rsplit_once(' ') {
+            Some((command, seed)) if seed.parse::<u64>().is_ok() => (command, seed.parse().ok()),
+            _ => (input, None),
+        };
         match input {
             "uci" => {
-                println!("uciok");
+                print_uci_options();
                 return wait_for_uci();
             }
             "crab" => {
-                bot_vs_bot();
+                let seed = seed.unwrap_or_else(rand::random);
+                bot_vs_bot(
+                    BotPlayer::SinglePlayer(SinglePlayerEvaluator::with_seed(seed)),
+                    BotPlayer::Search(seeded_search(seed)),
+                );
+            }
+            "crab mcts" => {
+                let seed = seed.unwrap_or_else(rand::random);
+                bot_vs_bot(
+                    BotPlayer::Mcts(CrabMcts::new()),
+                    BotPlayer::Search(seeded_search(seed)),
+                );
             }
             _ => {
                 println!("Unknown command. Try `uci`");
@@ -97,12 +209,58 @@ fn main() -> Result<(), ()> {
     // return Ok(());
 }
 
+fn search_for_mate(board: &Board, moves: usize, think_time: Duration) -> Option<ChessMove> {
+    // go mate - prove a mate against every defense or report that there isn't one
+    let start_time = Instant::now();
+    let mut solver = MateSolver::new();
+    match solver.find_mate(board, moves, think_time) {
+        (found_in, MateResult::Mate(line)) => {
+            let pv: Vec<String> = line.iter().map(|mv| mv.to_string()).collect();
+            println!(
+                "info depth {} score mate {} nodes {} time {} pv {}",
+                line.len(),
+                found_in,
+                solver.nodes(),
+                start_time.elapsed().as_millis(),
+                pv.join(" ")
+            );
+            line.first().copied()
+        }
+        (_, MateResult::NoMate) => {
+            println!("info string no mate in {moves}");
+            None
+        }
+        (1, MateResult::Unknown) => {
+            println!("info string gave up on mate in 1");
+            None
+        }
+        (tried, MateResult::Unknown) => {
+            println!(
+                "info string no mate in {} found, gave up on mate in {tried}",
+                tried - 1
+            );
+            None
+        }
+    }
+}
+
 fn wait_for_uci() -> Result<(), ()> {
     let mut game = Game::new();
     let mut evaluator = CrabChessSearch::new();
+    let mut mcts = CrabMcts::new();
+    // play with monte carlo tree search instead of alpha-beta search
+    let mut use_mcts = false;
     let mut move_depth = 9;
     let default_think_time: i32 = 4000;
     let mut think_time: i32 = default_think_time;
+    // UCI_LimitStrength uses UCI_Elo instead of Skill Level
+    let mut skill_level = MAX_SKILL_LEVEL;
+    let mut limit_strength = false;
+    let mut uci_elo = MAX_ELO;
+    let mut variety = 0;
+    let mut variety_moves = DEFAULT_VARIETY_MOVES;
+    // 0 means a new random seed for every game
+    let mut seed: Option<u64> = None;
     for line in io::stdin().lock().lines() {
         let msg: UciMessage = parse_one(&line.unwrap());
         debug!("Received message from stdin: {}", msg.to_string());
@@ -112,10 +270,81 @@ fn wait_for_uci() -> Result<(), ()> {
         match msg {
             UciMessage::Uci => {
                 // Initialize the UCI mode of the chess engine.
-                println!("uciok")
+                print_uci_options();
+            }
+            UciMessage::SetOption { name, value } => {
+                match (name.to_lowercase().as_str(), value) {
+                    ("threads", Some(threads)) => {
+                        if let Ok(threads) = threads.parse::<usize>() {
+                            evaluator.set_threads(threads.clamp(1, 64));
+                        }
+                    }
+                    ("contempt", Some(contempt)) => {
+                        if let Ok(contempt) = contempt.parse::<i32>() {
+                            evaluator.set_contempt(contempt.clamp(-MAX_CONTEMPT, MAX_CONTEMPT));
+                        }
+                    }
+                    ("uci_opponent", Some(opponent)) => {
+                        // an unrated opponent leaves contempt alone
+                        if let Some(contempt) = contempt_from_opponent(&opponent) {
+                            info!("Opponent {}, setting contempt to {}", opponent, contempt);
+                            evaluator.set_contempt(contempt);
+                        }
+                    }
+                    ("skill level", Some(level)) => {
+                        if let Ok(level) = level.parse::<u32>() {
+                            skill_level = level.min(MAX_SKILL_LEVEL);
+                        }
+                    }
+                    ("uci_limitstrength", Some(limit)) => {
+                        limit_strength = limit.eq_ignore_ascii_case("true");
+                    }
+                    ("uci_elo", Some(elo)) => {
+                        if let Ok(elo) = elo.parse::<u32>() {
+                            uci_elo = elo.clamp(MIN_ELO, MAX_ELO);
+                        }
+                    }
+                    ("variety", Some(margin)) => {
+                        if let Ok(margin) = margin.parse::<i32>() {
+                            variety = margin.clamp(0, MAX_VARIETY);
+                        }
+                    }
+                    ("variety moves", Some(moves)) => {
+                        if let Ok(moves) = moves.parse::<usize>() {
+                            variety_moves = moves;
+                        }
+                    }
+                    ("seed", Some(new_seed)) => {
+                        if let Ok(new_seed) = new_seed.parse::<u64>() {
+                            seed = Some(new_seed).filter(|new_seed| *new_seed != 0);
+                            evaluator.set_seed(seed);
+                        }
+                    }
+                    ("deterministic", Some(deterministic)) => {
+                        evaluator.set_deterministic(deterministic.eq_ignore_ascii_case("true"));
+                    }
+                    ("mode", Some(mode)) => match mode.to_lowercase().as_str() {
+                        "search" => use_mcts = false,
+                        "mcts" => use_mcts = true,
+                        _ => warn!("Unknown mode {}", mode),
+                    },
+                    ("driver", Some(driver)) => match driver.to_lowercase().as_str() {
+                        "alphabeta" => evaluator.set_driver(SearchDriver::AlphaBeta),
+                        "mtdf" => evaluator.set_driver(SearchDriver::Mtdf),
+                        _ => warn!("Unknown search driver {}", driver),
+                    },
+                    (_, value) => warn!("Unknown option {} = {:?}", name, value),
+                }
+                evaluator.set_variety(variety, variety_moves);
+                evaluator.set_skill(if limit_strength {
+                    Skill::from_elo(uci_elo)
+                } else {
+                    Skill::from_level(skill_level)
+                });
             }
             UciMessage::UciNewGame => {
                 game = Game::new();
+                evaluator.set_seed(seed);
                 think_time = default_think_time;
             }
             UciMessage::IsReady => {
@@ -129,10 +358,25 @@ fn wait_for_uci() -> Result<(), ()> {
                 // for mv in game.actions() {}
                 if startpos {
                     game = Game::new();
+                    evaluator.set_game_start(GameStart::default());
+                    mcts.set_game_start(GameStart::default());
                 }
                 if let Some(input_fen) = fen {
                     if let Ok(new_game) = Game::from_str(input_fen.as_str()) {
                         game = new_game;
+                        // the chess crate ignores the halfmove clock, it's the 5th field
+                        let halfmove_clock = input_fen
+                            .as_str()
+                            .split_whitespace()
+                            .nth(4)
+                            .and_then(|clock| clock.parse().ok())
+                            .unwrap_or(0);
+                        let game_start = GameStart {
+                            board: game.current_position(),
+                            halfmove_clock,
+                        };
+                        evaluator.set_game_start(game_start);
+                        mcts.set_game_start(game_start);
                     }
                 }
                 for each_move in moves.iter() {
@@ -157,7 +401,7 @@ fn wait_for_uci() -> Result<(), ()> {
                             black_time,
                             white_increment,
                             black_increment,
-                            moves_to_go,
+                            moves_to_go: _,
                         } => {
                             let remaining_time;
                             let my_increment;
@@ -200,18 +444,39 @@ fn wait_for_uci() -> Result<(), ()> {
                     }
                 }
 
+                let go_time = Instant::now();
+                let think_duration = Duration::from_millis(think_time as u64);
+                evaluator.set_node_budget(None);
+                let mut mate_move = None;
                 if let Some(sc) = search_control {
                     if let Some(set_depth) = sc.depth {
                         move_depth = set_depth as usize
                     }
+                    evaluator.set_node_budget(sc.nodes);
+                    if let Some(moves) = sc.mate {
+                        // half the time goes to looking for mate, so there's some left to find a
+                        // move to play if there isn't one
+                        mate_move = search_for_mate(
+                            &game.current_position(),
+                            moves as usize,
+                            think_duration / 2,
+                        );
+                    }
                 }
 
-                let (_value, mv) = evaluator.iterative_search_deepening(
-                    &game.current_position(),
-                    &game,
-                    move_depth,
-                    Duration::from_millis(think_time as u64),
-                );
+                let think_duration = think_duration.saturating_sub(go_time.elapsed());
+                let (_value, mv) = if let Some(mv) = mate_move {
+                    (0, mv)
+                } else if use_mcts {
+                    mcts.search(&game.current_position(), &game, think_duration)
+                } else {
+                    evaluator.iterative_search_deepening(
+                        &game.current_position(),
+                        &game,
+                        move_depth,
+                        think_duration,
+                    )
+                };
                 println!("bestmove {mv}");
                 game.make_move(mv);
 
@@ -232,14 +497,39 @@ fn wait_for_uci() -> Result<(), ()> {
     Ok(())
 }
 
-fn bot_vs_bot() {
+fn seeded_search(seed: u64) -> Box<CrabChessSearch> {
+    let mut search = Box::<CrabChessSearch>::default();
+    search.set_seed(Some(seed));
+    search
+}
+
+// anything that can play one side of a bot_vs_bot game
+enum BotPlayer {
+    SinglePlayer(SinglePlayerEvaluator),
+    Search(Box<CrabChessSearch>),
+    Mcts(CrabMcts),
+}
+
+impl BotPlayer {
+    fn choose_move(&mut self, game: &Game, move_duration: Duration) -> (i32, ChessMove) {
+        let board = game.current_position();
+        match self {
+            BotPlayer::SinglePlayer(evaluator) => {
+                (0, evaluator.top_level_search(&board, 5, move_duration))
+            }
+            BotPlayer::Search(evaluator) => {
+                evaluator.iterative_search_deepening(&board, game, 12, move_duration)
+            }
+            BotPlayer::Mcts(mcts) => mcts.search(&board, game, move_duration),
+        }
+    }
+}
+
+fn bot_vs_bot(mut white: BotPlayer, mut black: BotPlayer) {
     // let mut white_evaluator: evaluator::CrabChessEvaluator = CrabChessEvaluator::new();
-    let mut white_evaluator = SinglePlayerEvaluator::new();
-    let mut black_evaluator = CrabChessSearch::new();
     let mut board: Board;
-    let move_depth: usize = 12;
     let mut game = Game::new();
-    let mut move_duration = Duration::from_millis(300);
+    let move_duration = Duration::from_millis(300);
     loop {
         board = game.current_position();
         debug!(
@@ -250,29 +540,18 @@ fn bot_vs_bot() {
         // println!("{:?}", game.actions());
         let to_move = board.side_to_move();
         info!("Move {} - {:?} to move", game.actions().len() + 1, to_move);
-        if !game.result().is_none() {
+        if game.result().is_some() {
             info!("Game Over");
             break;
         } else if game.can_declare_draw() {
             info!("Draw");
             break;
         }
-        match to_move {
-            White => {
-                let mv = white_evaluator.top_level_search(&board, 5, move_duration);
-                info!("{:?} AI Move: {}", to_move, mv);
-                game.make_move(mv);
-            }
-            Black => {
-                let (value, mv) = black_evaluator.iterative_search_deepening(
-                    &board,
-                    &game,
-                    move_depth,
-                    move_duration,
-                );
-                info!("{:?} AI Move: {} @ {}", to_move, mv, value);
-                game.make_move(mv);
-            }
-        }
+        let (value, mv) = match to_move {
+            White => white.choose_move(&game, move_duration),
+            Black => black.choose_move(&game, move_duration),
+        };
+        info!("{:?} AI Move: {} @ {}", to_move, mv, value);
+        game.make_move(mv);
     }
 }
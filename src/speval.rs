@@ -1,17 +1,36 @@
 use chess::{Board, BoardStatus, ChessMove, MoveGen};
 use log::info;
+use rand::rngs::StdRng;
 use rand::seq::IteratorRandom;
 use rand::seq::SliceRandom;
+use rand::SeedableRng;
 use std::time::{Duration, Instant};
-pub struct SinglePlayerEvaluator {}
+pub struct SinglePlayerEvaluator {
+    rng: StdRng,
+}
+
+impl Default for SinglePlayerEvaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl SinglePlayerEvaluator {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        // plays the same moves every time
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
     }
 
     pub fn top_level_search(
-        &self,
+        &mut self,
         board: &Board,
         depth: usize,
         max_search_time: Duration,
@@ -36,17 +55,14 @@ impl SinglePlayerEvaluator {
             start_time.elapsed()
         );
         let move_gen = MoveGen::new_legal(board);
-        return move_gen
-            .into_iter()
-            .choose(&mut rand::thread_rng())
-            .unwrap();
+        move_gen.into_iter().choose(&mut self.rng).unwrap()
         // println!(
         //     "{:?}",
         //     move_gen.choose(&mut rand::thread_rng())
         // );
     }
 
-    fn search(&self, board: &Board, depth: usize, kill_time: Instant) -> Option<ChessMove> {
+    fn search(&mut self, board: &Board, depth: usize, kill_time: Instant) -> Option<ChessMove> {
         if depth == 0 || kill_time.elapsed() > Duration::ZERO {
             return None;
         }
@@ -56,8 +72,8 @@ impl SinglePlayerEvaluator {
         // in random order to spice it up
         let mut legal_moves_vec = legal_moves
             .into_iter()
-            .choose_multiple(&mut rand::thread_rng(), num_legal_moves);
-        legal_moves_vec.shuffle(&mut rand::thread_rng());
+            .choose_multiple(&mut self.rng, num_legal_moves);
+        legal_moves_vec.shuffle(&mut self.rng);
         for possible_move in legal_moves_vec {
             // make a new board and make a move
             let possible_board = board.make_move_new(possible_move);
@@ -74,8 +90,7 @@ impl SinglePlayerEvaluator {
                     // instead let's have them make a totally random legal move
                     possible_board.make_move_new(
                         MoveGen::new_legal(&possible_board)
-                            .into_iter()
-                            .choose(&mut rand::thread_rng())
+                            .choose(&mut self.rng)
                             .unwrap(),
                     )
                 }
@@ -87,6 +102,6 @@ impl SinglePlayerEvaluator {
             }
         }
         // couldn't find a path to checkmate
-        return None;
+        None
     }
 }